@@ -0,0 +1,243 @@
+//! Runs a `.circ` file without opening a window
+//!
+//! usage: zl001-run <file.circ> [-n steps] [-q] [-b mc:breakpoint]... [-t trace.jsonl] [-v ports.vcd]
+//!                  [-l mc:object]... [-o mc:object]...
+//! compiles every microcontroller, then steps until they all halt, the circuit
+//! deadlocks, a breakpoint is hit or `steps` is reached, printing each value passed between ports
+//! and the registers and execution stats of every microcontroller at the end.
+//! if the circuit deadlocks the port each microcontroller is waiting on is printed.
+//! exits with 0 if every microcontroller halted, 1 on a load or compile error,
+//! 2 on a deadlock or fault, 3 if it ran out of steps and 4 if it stopped at a breakpoint.
+//! a breakpoint is the index of a microcontroller and a condition, such as `0:line 4`,
+//! `1:R1 == 5` or `2:io2 written`.
+//! `-t` writes a trace of every step, see `zl001::simulation::trace`,
+//! `-v` writes the io ports as a VCD, see `zl001::simulation::vcd`.
+//! `-l` runs a microcontroller from an object file instead of its code, `-o` writes the compiled
+//! program of a microcontroller to an object file, see `zl001::microcontroller::object`
+
+use zl001::microcontroller::assembler::Register;
+use zl001::microcontroller::Breakpoint;
+use zl001::simulation::{CircuitFile, Tracer, VcdWriter};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+const DEFAULT_STEPS : usize = 1000;
+const USAGE : &str = "usage: zl001-run <file.circ> [-n steps] [-q] [-b mc:breakpoint]... [-t trace.jsonl] [-v ports.vcd] [-l mc:object]... [-o mc:object]...";
+
+struct Args {
+    path : String,
+    steps : usize,
+    quiet : bool,
+    breakpoints : Vec<(usize, Breakpoint)>,
+    trace : Option<String>,
+    vcd : Option<String>,
+    /// object files to run instead of compiling the code
+    load_objects : Vec<(usize, String)>,
+    /// object files to write the compiled programs to
+    write_objects : Vec<(usize, String)>,
+}
+
+/// parse a breakpoint as `mc:condition`
+fn parse_breakpoint(text : &str) -> Result<(usize, Breakpoint), String> {
+    let (mc, bp) = text.split_once(':').ok_or(format!("breakpoint needs a microcontroller index: {}", text))?;
+    let mc = mc.trim().parse().map_err(|_| format!("not a microcontroller index: {}", mc))?;
+    Ok((mc, bp.parse()?))
+}
+
+/// parse an object file as `mc:path`
+fn parse_object(text : &str) -> Result<(usize, String), String> {
+    let (mc, path) = text.split_once(':').ok_or(format!("object needs a microcontroller index: {}", text))?;
+    let mc = mc.trim().parse().map_err(|_| format!("not a microcontroller index: {}", mc))?;
+    Ok((mc, path.to_string()))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut path = None;
+    let mut steps = DEFAULT_STEPS;
+    let mut quiet = false;
+    let mut breakpoints = Vec::new();
+    let mut trace = None;
+    let mut vcd = None;
+    let mut load_objects = Vec::new();
+    let mut write_objects = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" | "--steps" => {
+                let n = args.next().ok_or("-n needs a number of steps")?;
+                steps = n.parse().map_err(|_| format!("not a number of steps: {}", n))?;
+            },
+            "-q" | "--quiet" => quiet = true,
+            "-b" | "--break" => {
+                let bp = args.next().ok_or("-b needs a breakpoint")?;
+                breakpoints.push(parse_breakpoint(&bp)?);
+            },
+            "-t" | "--trace" => trace = Some(args.next().ok_or("-t needs a file to write the trace to")?),
+            "-v" | "--vcd" => vcd = Some(args.next().ok_or("-v needs a file to write the VCD to")?),
+            "-l" | "--load-object" => load_objects.push(parse_object(&args.next().ok_or("-l needs an object file")?)?),
+            "-o" | "--write-object" => write_objects.push(parse_object(&args.next().ok_or("-o needs an object file")?)?),
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}\n{}", arg, USAGE)),
+        }
+    }
+    Ok(Args {
+        path : path.ok_or(USAGE)?,
+        steps,
+        quiet,
+        breakpoints,
+        trace,
+        vcd,
+        load_objects,
+        write_objects,
+    })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(1);
+        }
+    };
+    let mut sim = match CircuitFile::load(Path::new(&args.path)).and_then(|f| f.to_simulation()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("error loading {}: {}", args.path, e);
+            return ExitCode::from(1);
+        }
+    };
+
+    for (mc, bp) in args.breakpoints.iter() {
+        if *mc >= sim.mcs().len() {
+            eprintln!("breakpoint on mc {}, there are only {}", mc, sim.mcs().len());
+            return ExitCode::from(1);
+        }
+        sim.mc_mut(*mc).add_breakpoint(*bp);
+    }
+    for (mc, _) in args.load_objects.iter().chain(args.write_objects.iter()) {
+        if *mc >= sim.mcs().len() {
+            eprintln!("object for mc {}, there are only {}", mc, sim.mcs().len());
+            return ExitCode::from(1);
+        }
+    }
+
+    let mut compiled = true;
+    for (i, result) in sim.compile().into_iter().enumerate() {
+        //the code of a microcontroller run from an object doesn't matter
+        if args.load_objects.iter().any(|(mc, _)| *mc == i) {
+            continue;
+        }
+        match result {
+            Ok(warnings) => {
+                for w in warnings {
+                    eprintln!("mc {}: {}", i, w);
+                }
+            },
+            Err(errors) => {
+                compiled = false;
+                for e in errors {
+                    eprintln!("mc {}: {}", i, e);
+                }
+            },
+        }
+    }
+    if !compiled {
+        return ExitCode::from(1);
+    }
+
+    for (mc, path) in args.load_objects.iter() {
+        let loaded = fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| sim.mc_mut(*mc).load_object(&bytes));
+        if let Err(e) = loaded {
+            eprintln!("error loading object {} for mc {}: {}", path, mc, e);
+            return ExitCode::from(1);
+        }
+    }
+    for (mc, path) in args.write_objects.iter() {
+        let written = sim.mcs()[*mc].object().map_err(|e| e.to_string()).and_then(|bytes| fs::write(path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("error writing object {} for mc {}: {}", path, mc, e);
+            return ExitCode::from(1);
+        }
+    }
+
+    if let Some(path) = &args.trace {
+        match Tracer::create(Path::new(path)) {
+            Ok(tracer) => sim.set_tracer(tracer),
+            Err(e) => {
+                eprintln!("error creating trace {}: {}", path, e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+    if let Some(path) = &args.vcd {
+        match VcdWriter::create(Path::new(path)) {
+            Ok(vcd) => sim.set_vcd(vcd),
+            Err(e) => {
+                eprintln!("error creating VCD {}: {}", path, e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    let mut step = 0;
+    while step < args.steps && !sim.stopped() {
+        step += 1;
+        for t in sim.step() {
+            if !args.quiet {
+                println!("step {}: mc {} io{} -> mc {} io{}: {}",
+                         step, t.from.get_mc_i(), t.from.get_io_i(), t.to.get_mc_i(), t.to.get_io_i(), t.value);
+            }
+        }
+        if sim.stalled() || !sim.breakpoint_hits().is_empty() {
+            break;
+        }
+    }
+
+    if let Some(Err(e)) = sim.take_tracer().map(|t| t.finish()) {
+        eprintln!("error writing trace: {}", e);
+    }
+    if let Some(Err(e)) = sim.take_vcd().map(|v| v.finish()) {
+        eprintln!("error writing VCD: {}", e);
+    }
+
+    for (i, mc) in sim.mcs().iter().enumerate() {
+        let reg = |r| mc.get_register_value(r).unwrap_or(0);
+        let state = match mc.fault() {
+            Some(fault) => format!("fault: {}", fault),
+            None if mc.halted() => String::from("halted"),
+            None => String::from("running"),
+        };
+        let stats = mc.stats();
+        println!("mc {}: PC {} R1 {} R2 {} RT {} ({})",
+                 i, reg(Register::PC), reg(Register::R1), reg(Register::R2), reg(Register::RT), state);
+        println!("      instructions {} read blocked {} write blocked {} stopped {}",
+                 stats.instructions, stats.read_blocked, stats.write_blocked, stats.stopped);
+    }
+
+    if !sim.breakpoint_hits().is_empty() {
+        for (mc, bp) in sim.breakpoint_hits() {
+            println!("mc {} hit breakpoint {} after {} steps", mc, bp, step);
+        }
+        ExitCode::from(4)
+    } else if let Some(blocked) = sim.deadlock() {
+        println!("deadlocked after {} steps", step);
+        for b in blocked {
+            println!("  {}", b);
+        }
+        ExitCode::from(2)
+    } else if sim.mcs().iter().any(|mc| mc.fault().is_some()) {
+        println!("faulted after {} steps", step);
+        ExitCode::from(2)
+    } else if sim.stopped() {
+        println!("halted after {} steps", step);
+        ExitCode::SUCCESS
+    } else {
+        println!("still running after {} steps", step);
+        ExitCode::from(3)
+    }
+}
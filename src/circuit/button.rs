@@ -33,6 +33,10 @@ impl Button {
         &self.text
     }
 
+    pub fn set_text(&mut self, text : String) {
+        self.text = text;
+    }
+
     /// set clicked to true for a single frame if button is clicked
     pub fn update(&mut self, mouse : &Mouse, prev_mouse : &Mouse) {
         self.selected = self.game_obj.draw_rect.contains(&Vec2::new(mouse.x as f64, mouse.y as f64));
@@ -1,14 +1,34 @@
 use crate::{
-    GameObject, geometry::*, input::Mouse, TextureManager, FontManager, resource::Font, microcontroller::Microcontroller};
+    GameObject, geometry::*, input::Mouse, TextureManager, FontManager, resource::Font, microcontroller::{Microcontroller, McModel}};
+use crate::microcontroller::assembler::Register;
 use super::button::Button;
-use super::circuit_helper::McConnection;
+use crate::simulation::{McConnection, BlockedPort};
 use sdl2::video::Window;
 use sdl2::render::Canvas;
+use sdl2::pixels::Color;
 
 use std::collections::HashMap;
 
 const CONNECTION_THICKNESS : f64 = 10.0;
 const CONNECTION_BTN_SIZE : f64 = 20.0;
+const FAULT_TEXT_HEIGHT : u32 = 16;
+const FAULT_TEXT_COLOUR : Color = Color::RGB(220, 50, 40);
+const BLOCKED_PORT_SIZE : f64 = 16.0;
+const DEADLOCK_TEXT_HEIGHT : u32 = 20;
+const STATS_TEXT_HEIGHT : u32 = 14;
+const STATS_TEXT_COLOUR : Color = Color::RGB(200, 200, 200);
+/// top left of the inspector, the cycle count is drawn just above it
+const INSPECTOR_X : f64 = 340.0;
+const INSPECTOR_Y : f64 = 205.0;
+const INSPECTOR_TEXT_HEIGHT : u32 = 16;
+const INSPECTOR_TEXT_COLOUR : Color = Color::RGB(200, 200, 200);
+const INSPECTOR_LINE_SPACING : f64 = 18.0;
+const INSPECTOR_MAX_CODE_LEN : usize = 20;
+const SPEED_TEXT_HEIGHT : u32 = 20;
+const SPEED_TEXT_COLOUR : Color = Color::RGB(200, 200, 200);
+const DEFAULT_STEPS_PER_SECOND : f64 = 4.0;
+const MIN_STEPS_PER_SECOND : f64 = 1.0;
+const MAX_STEPS_PER_SECOND : f64 = 512.0;
 
 #[derive(PartialEq)]
 enum State {
@@ -21,17 +41,36 @@ enum State {
 pub struct Gui  {
     add_mc_btn : Button,
     add_con_btn : Button,
+    model_btn : Button,
     clear_btn : Button,
     save_btn : Button,
     load_btn : Button,
+    trace_btn : Button,
+    vcd_btn : Button,
     compile_btn : Button,
     step_btn : Button,
+    step_back_btn : Button,
+    run_btn : Button,
+    pause_btn : Button,
+    fast_btn : Button,
+    to_halt_btn : Button,
+    slower_btn : Button,
+    faster_btn : Button,
+    steps_per_second : f64,
     remove_mc_btn : Button,
     code_mc_btn : Button,
+    step_mc_btn : Button,
+    overflow_mc_btn : Button,
     prev_mouse : Mouse,
     mc_btns : Vec<Button>,
     mc_cons : Vec<GameObject>,
+    mc_faults : Vec<(Vec2, String)>,
+    mc_stats : Vec<(Vec2, String)>,
+    cycles : usize,
+    /// highlight over each port stuck in a deadlock
+    blocked_ports : Vec<Rect>,
     state : State,
+    model : &'static McModel,
     placed_rect : Option<Rect>,
     connection : Option<(McConnection, McConnection)>,
     clicked_connection : Option<McConnection>,
@@ -39,6 +78,9 @@ pub struct Gui  {
     prev_click_pos : Option<Vec2>,
     current_mouse_pos : Vec2,
     mc_selected_index : Option<usize>, 
+    /// the microcontroller shown in the inspector, the last one clicked
+    inspected : Option<usize>,
+    inspector_lines : Vec<String>,
     box_tex : GameObject,
     font : Font,
 }
@@ -47,31 +89,62 @@ impl Gui {
     pub fn new(btn_obj : GameObject, font : Font) -> Self {
         let add_mc_btn = Button::new(btn_obj.clone(), Some(Rect::new(5.0, 3.0, 130.0, 26.0)), "add circuit".to_string());
         let add_con_btn = Button::new(btn_obj.clone(), Some(Rect::new(140.0, 3.0, 100.0, 26.0)), "add conn".to_string());
+        let model = McModel::default_model();
+        let model_btn = Button::new(btn_obj.clone(), Some(Rect::new(245.0, 3.0, 90.0, 26.0)), model.name.to_string());
         
         let save_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 10.0, 75.0, 25.0)), "save".to_string());
         let load_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 40.0, 75.0, 25.0)), "load".to_string());
         let clear_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 70.0, 75.0, 25.0)), "clear".to_string());
+        let trace_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 100.0, 75.0, 25.0)), "trace".to_string());
+        let vcd_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 130.0, 75.0, 25.0)), "vcd".to_string());
 
         let compile_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 415.0, 75.0, 25.0)), "compile".to_string());
         let step_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 445.0, 75.0, 25.0)), "step".to_string());
+        let step_back_btn = Button::new(btn_obj.clone(), Some(Rect::new(480.0, 385.0, 75.0, 25.0)), "back".to_string());
+        let run_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 295.0, 75.0, 25.0)), "run".to_string());
+        let pause_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 325.0, 75.0, 25.0)), "pause".to_string());
+        let fast_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 355.0, 75.0, 25.0)), "fast".to_string());
+        let to_halt_btn = Button::new(btn_obj.clone(), Some(Rect::new(560.0, 385.0, 75.0, 25.0)), "to halt".to_string());
+        let slower_btn = Button::new(btn_obj.clone(), Some(Rect::new(470.0, 445.0, 25.0, 25.0)), "-".to_string());
+        let faster_btn = Button::new(btn_obj.clone(), Some(Rect::new(530.0, 445.0, 25.0, 25.0)), "+".to_string());
         
         let remove_mc_btn = Button::new(btn_obj.clone(), Some(Rect::new(100.0, 400.0, 60.0, 30.0)), "del".to_string());
         let code_mc_btn = Button::new(btn_obj.clone(), Some(Rect::new(180.0, 400.0, 60.0, 30.0)), "code".to_string());
+        let step_mc_btn = Button::new(btn_obj.clone(), Some(Rect::new(260.0, 400.0, 60.0, 30.0)), "step".to_string());
+        let overflow_mc_btn = Button::new(btn_obj.clone(), Some(Rect::new(340.0, 400.0, 120.0, 30.0)), "wrap".to_string());
         
         Gui {
             add_mc_btn,
             add_con_btn,
+            model_btn,
             clear_btn,
             save_btn,
             load_btn,
+            trace_btn,
+            vcd_btn,
             compile_btn,
             step_btn,
+            step_back_btn,
+            run_btn,
+            pause_btn,
+            fast_btn,
+            to_halt_btn,
+            slower_btn,
+            faster_btn,
+            steps_per_second : DEFAULT_STEPS_PER_SECOND,
             remove_mc_btn,
             code_mc_btn,
+            step_mc_btn,
+            overflow_mc_btn,
             mc_btns : Vec::new(),
             mc_cons : Vec::new(), 
+            mc_faults : Vec::new(),
+            mc_stats : Vec::new(),
+            cycles : 0,
+            blocked_ports : Vec::new(),
             prev_mouse : Mouse::new(),
             state : State::Default,
+            model,
             placed_rect : None,
             connection : None,
             clicked_connection : None,
@@ -79,6 +152,8 @@ impl Gui {
             prev_click_pos : None,
             current_mouse_pos : Vec2::new(0.0, 0.0),
             mc_selected_index : None,
+            inspected : None,
+            inspector_lines : Vec::new(),
             box_tex : btn_obj,
             font,
         }
@@ -87,17 +162,49 @@ impl Gui {
     pub fn draw<'sdl2, TTex, TFont>(&mut self, canvas : &mut Canvas<Window>,  texture_manager : &'sdl2 TextureManager<TTex>, font_manager : &'sdl2 FontManager<TFont>) -> Result<(), String> {
         self.add_mc_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.add_con_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.model_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.clear_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.save_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.load_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.trace_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.vcd_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.compile_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
         self.step_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.step_back_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.run_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.pause_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.fast_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.to_halt_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.slower_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        self.faster_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+        font_manager.draw(canvas, &self.font, &format!("{}/s", self.steps_per_second), SPEED_TEXT_HEIGHT, Vec2::new(470.0, 420.0), SPEED_TEXT_COLOUR)?;
+        font_manager.draw(canvas, &self.font, &format!("cycle {}", self.cycles), SPEED_TEXT_HEIGHT, Vec2::new(INSPECTOR_X, INSPECTOR_Y - SPEED_TEXT_HEIGHT as f64 - 5.0), SPEED_TEXT_COLOUR)?;
         for mc in self.mc_btns.as_slice() {
             mc.draw(canvas, texture_manager, font_manager, &self.font)?;
         }
         for con in self.mc_cons.as_slice() {
             texture_manager.draw(canvas, con)?;
         }
+        for (pos, text) in self.mc_faults.iter() {
+            font_manager.draw(canvas, &self.font, text, FAULT_TEXT_HEIGHT, *pos, FAULT_TEXT_COLOUR)?;
+        }
+        for (pos, text) in self.mc_stats.iter() {
+            font_manager.draw(canvas, &self.font, text, STATS_TEXT_HEIGHT, *pos, STATS_TEXT_COLOUR)?;
+        }
+        if !self.inspector_lines.is_empty() {
+            let rect = Rect::new(INSPECTOR_X, INSPECTOR_Y, 210.0, self.inspector_lines.len() as f64 * INSPECTOR_LINE_SPACING + 10.0);
+            texture_manager.draw_rect(canvas, &rect, &Rect::new(30.0, 40.0, 38.0, 255.0))?;
+            for (i, line) in self.inspector_lines.iter().enumerate() {
+                let pos = Vec2::new(rect.x + 5.0, rect.y + 5.0 + i as f64 * INSPECTOR_LINE_SPACING);
+                font_manager.draw(canvas, &self.font, line, INSPECTOR_TEXT_HEIGHT, pos, INSPECTOR_TEXT_COLOUR)?;
+            }
+        }
+        for rect in self.blocked_ports.iter() {
+            texture_manager.draw_rect(canvas, rect, &Rect::new(220.0, 50.0, 40.0, 255.0))?;
+        }
+        if !self.blocked_ports.is_empty() {
+            font_manager.draw(canvas, &self.font, "deadlock", DEADLOCK_TEXT_HEIGHT, Vec2::new(345.0, 6.0), FAULT_TEXT_COLOUR)?;
+        }
         match self.state {
             State::AddMc => {
                 if let Some(p) = self.prev_click_pos {
@@ -110,6 +217,8 @@ impl Gui {
             State::McMenu => {
                 self.remove_mc_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
                 self.code_mc_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+                self.step_mc_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
+                self.overflow_mc_btn.draw(canvas, texture_manager, font_manager, &self.font)?;
             },
 
             State::AddCon => {
@@ -129,7 +238,9 @@ impl Gui {
         Ok(())
     }
 
-    pub fn update(&mut self, mouse : &Mouse, mcs : &Vec<Microcontroller>, connections : &HashMap<McConnection, McConnection>, modified : bool) {
+    /// `mc_objs` has the `GameObject` of the `Microcontroller` at the same index,
+    /// `blocked` are the ports to highlight if the circuit has deadlocked
+    pub fn update(&mut self, mouse : &Mouse, mcs : &[Microcontroller], mc_objs : &[GameObject], connections : &HashMap<McConnection, McConnection>, blocked : &[BlockedPort], modified : bool) {
         if self.state == State::Default {
             self.mc_selected_index = None;
         }
@@ -138,15 +249,18 @@ impl Gui {
         self.connection = None;
 
         if modified {
+            if self.inspected.is_some_and(|i| i >= mcs.len()) {
+                self.inspected = None;
+            }
             self.mc_btns.clear();
             self.con_btns.clear();
-            for (i, mc) in mcs.iter().enumerate() {
-                self.mc_btns.push(Button::new(mc.get_game_object().clone(), None, "".to_string()));
+            for (i, (mc, mc_obj)) in mcs.iter().zip(mc_objs).enumerate() {
+                self.mc_btns.push(Button::new(mc_obj.clone(), None, "".to_string()));
                 for j in 0..mc.io_count() {
                     let con = McConnection::new(i, j);
                     let p = self.get_io_out_pos(&con);
                     let rect = Rect::new(p.x - CONNECTION_BTN_SIZE/2.0, p.y - CONNECTION_BTN_SIZE/2.0, CONNECTION_BTN_SIZE, CONNECTION_BTN_SIZE);
-                    let mut go = mc.get_game_object().clone();
+                    let mut go = mc_obj.clone();
                     go.draw_rect = rect;
                     self.con_btns.push(
                         (
@@ -162,7 +276,46 @@ impl Gui {
             }
         }
 
+        self.mc_faults.clear();
+        self.mc_stats.clear();
+        for (mc, mc_obj) in mcs.iter().zip(mc_objs) {
+            let rect = &mc_obj.draw_rect;
+            if let Some(fault) = mc.fault() {
+                self.mc_faults.push((Vec2::new(rect.x, rect.y + rect.h), format!("fault: {}", fault)));
+            }
+            let stats = mc.stats();
+            self.mc_stats.push((Vec2::new(rect.x, rect.y - STATS_TEXT_HEIGHT as f64),
+                                format!("ins {} rd {} wr {} idle {}", stats.instructions, stats.read_blocked, stats.write_blocked, stats.stopped)));
+        }
+
+        self.inspector_lines = match self.inspected.and_then(|i| mcs.get(i).map(|mc| (i, mc))) {
+            Some((i, mc)) => inspector_lines(i, mc),
+            None => Vec::new(),
+        };
+
+        self.blocked_ports.clear();
+        for b in blocked {
+            let p = self.get_io_out_pos(&b.port);
+            self.blocked_ports.push(Rect::new(p.x - BLOCKED_PORT_SIZE/2.0, p.y - BLOCKED_PORT_SIZE/2.0, BLOCKED_PORT_SIZE, BLOCKED_PORT_SIZE));
+        }
+
         self.btn_update(mouse);
+
+        //show the overflow mode of the chip the menu is open for
+        if let Some(mc) = self.mc_selected_index.and_then(|i| mcs.get(i)) {
+            self.overflow_mc_btn.set_text(mc.overflow_mode().name().to_string());
+        }
+
+        if self.model_btn.clicked() {
+            self.model = self.model.next();
+            self.model_btn.set_text(self.model.name.to_string());
+        }
+        if self.slower_btn.clicked() {
+            self.steps_per_second = (self.steps_per_second / 2.0).max(MIN_STEPS_PER_SECOND);
+        }
+        if self.faster_btn.clicked() {
+            self.steps_per_second = (self.steps_per_second * 2.0).min(MAX_STEPS_PER_SECOND);
+        }
             
         if self.add_mc_btn.clicked() {
             if self.state == State::Default {
@@ -254,22 +407,35 @@ impl Gui {
                 if mc.clicked() {
                     self.prev_mouse.left_click = true;
                     self.mc_selected_index = Some(i);
+                    self.inspected = Some(i);
                     self.state = State::McMenu;
                     //change pos of btn to be under circuit
                 }
             }
         } else if self.state ==  State::McMenu {
             self.remove_mc_btn.update(mouse, &self.prev_mouse);
-            self.code_mc_btn.update(mouse, &self.prev_mouse)
+            self.code_mc_btn.update(mouse, &self.prev_mouse);
+            self.step_mc_btn.update(mouse, &self.prev_mouse);
+            self.overflow_mc_btn.update(mouse, &self.prev_mouse);
         }
 
         self.add_mc_btn.update(mouse, &self.prev_mouse);
         self.add_con_btn.update(mouse, &self.prev_mouse);
+        self.model_btn.update(mouse, &self.prev_mouse);
         self.clear_btn.update(mouse, &self.prev_mouse);
         self.save_btn.update(mouse, &self.prev_mouse);
         self.load_btn.update(mouse, &self.prev_mouse);
+        self.trace_btn.update(mouse, &self.prev_mouse);
+        self.vcd_btn.update(mouse, &self.prev_mouse);
         self.compile_btn.update(mouse, &self.prev_mouse);
         self.step_btn.update(mouse, &self.prev_mouse);
+        self.step_back_btn.update(mouse, &self.prev_mouse);
+        self.run_btn.update(mouse, &self.prev_mouse);
+        self.pause_btn.update(mouse, &self.prev_mouse);
+        self.fast_btn.update(mouse, &self.prev_mouse);
+        self.to_halt_btn.update(mouse, &self.prev_mouse);
+        self.slower_btn.update(mouse, &self.prev_mouse);
+        self.faster_btn.update(mouse, &self.prev_mouse);
     }
 
     fn circ_place_mode_update(&mut self, mouse : &Mouse) {
@@ -324,8 +490,9 @@ impl Gui {
         }
     }
 
-    pub fn add_circ_request(&self) -> Option<Rect> {
-        self.placed_rect.clone()
+    /// the area and model of a microcontroller to add
+    pub fn add_circ_request(&self) -> Option<(Rect, &'static McModel)> {
+        self.placed_rect.clone().map(|rect| (rect, self.model))
     }
 
     pub fn add_con_request(&self) -> Option<(McConnection, McConnection)> {
@@ -336,6 +503,7 @@ impl Gui {
         if self.remove_mc_btn.clicked() {
             let index = self.mc_selected_index;
             self.mc_selected_index = None;
+            self.inspected = None;
             self.state = State::Default;
             self.remove_mc_btn.reset();
             return index;
@@ -356,6 +524,32 @@ impl Gui {
         None
     }
 
+    /// the microcontroller to step on its own
+    pub fn step_mcs_index(&mut self) -> Option<usize> {
+        if self.step_mc_btn.clicked() {
+            let index = self.mc_selected_index;
+            self.mc_selected_index = None;
+            self.state = State::Default;
+            self.step_mc_btn.reset();
+            return index;
+        }
+
+        None
+    }
+
+    /// the microcontroller to change to the next `OverflowMode`
+    pub fn overflow_mcs_index(&mut self) -> Option<usize> {
+        if self.overflow_mc_btn.clicked() {
+            let index = self.mc_selected_index;
+            self.mc_selected_index = None;
+            self.state = State::Default;
+            self.overflow_mc_btn.reset();
+            return index;
+        }
+
+        None
+    }
+
     pub fn clear_circuit(&self) -> bool {
         self.clear_btn.clicked()
     }
@@ -368,6 +562,24 @@ impl Gui {
         self.load_btn.clicked()
     }
 
+    pub fn toggle_trace(&self) -> bool {
+        self.trace_btn.clicked()
+    }
+
+    /// show if a trace is being recorded
+    pub fn set_tracing(&mut self, tracing : bool) {
+        self.trace_btn.set_text(if tracing { "tracing" } else { "trace" }.to_string());
+    }
+
+    pub fn toggle_vcd(&self) -> bool {
+        self.vcd_btn.clicked()
+    }
+
+    /// show if a VCD is being written
+    pub fn set_writing_vcd(&mut self, writing : bool) {
+        self.vcd_btn.set_text(if writing { "writing" } else { "vcd" }.to_string());
+    }
+
     pub fn compile(&self) -> bool {
         self.compile_btn.clicked()
     }
@@ -375,4 +587,63 @@ impl Gui {
     pub fn step(&self) -> bool {
         self.step_btn.clicked()
     }
+
+    pub fn step_back(&self) -> bool {
+        self.step_back_btn.clicked()
+    }
+
+    pub fn run(&self) -> bool {
+        self.run_btn.clicked()
+    }
+
+    pub fn pause(&self) -> bool {
+        self.pause_btn.clicked()
+    }
+
+    pub fn fast_forward(&self) -> bool {
+        self.fast_btn.clicked()
+    }
+
+    pub fn run_until_halt(&self) -> bool {
+        self.to_halt_btn.clicked()
+    }
+
+    /// set the count of circuit cycles to show
+    pub fn set_cycles(&mut self, cycles : usize) {
+        self.cycles = cycles;
+    }
+
+    /// how fast to step when running, set with the speed buttons
+    pub fn steps_per_second(&self) -> f64 {
+        self.steps_per_second
+    }
+}
+
+/// the text of the inspector for a microcontroller, its registers, ports and the line it is on
+fn inspector_lines(i : usize, mc : &Microcontroller) -> Vec<String> {
+    let reg = |r| mc.get_register_value(r).unwrap_or(0);
+    let mut lines = vec![
+        format!("mc {} {} {}", i, mc.model().name, mc.overflow_mode().name()),
+        format!("PC {} R1 {} R2 {} RT {}", reg(Register::PC), reg(Register::R1), reg(Register::R2), reg(Register::RT)),
+    ];
+    for io in 0..mc.io_count() {
+        let status = if mc.io_read_in_ready(io) {
+            " reading"
+        } else if mc.io_read_out_ready(io) {
+            " writing"
+        } else {
+            ""
+        };
+        lines.push(format!("IO{} {}{}", io, mc.io_value(io), status));
+    }
+    lines.push(match (mc.fault(), mc.source_line()) {
+        (Some(fault), _) => format!("fault: {}", fault),
+        _ if mc.halted() => String::from("halted"),
+        (None, Some(line)) => {
+            let code : String = mc.source_line_code().unwrap_or("").chars().take(INSPECTOR_MAX_CODE_LEN).collect();
+            format!("{}: {}", line + 1, code)
+        },
+        (None, None) => String::from("not compiled"),
+    });
+    lines
 }
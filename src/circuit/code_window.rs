@@ -1,5 +1,7 @@
 //! handles the text editor to let the user input code that can be assembled into a `program`
 
+use crate::microcontroller::formatter::format_code_lines;
+
 use crate::TextDraw;
 use crate::input::Typing;
 use crate::FontManager;
@@ -16,6 +18,8 @@ const BACKSPACE_REPEAT_SPEED : f64 = 0.05;
 const CURSOR_BLINK_DELAY : f64 = 1.2;
 const CURSOR_BLINK_DURATION : f64 = 0.6;
 const CODE_WINDOW_TEXT_COLOUR : Color = Color::RGB(109, 205, 18);
+const BREAKPOINT_COLOUR : Color = Color::RGB(220, 50, 40);
+const BREAKPOINT_MARGIN : f64 = 16.0;
 
 /// Holds a `String` of code as well as info on user input
 pub struct CodeWindow<'a> {
@@ -32,6 +36,10 @@ pub struct CodeWindow<'a> {
     cursor_blink_updated : bool,
     mono_font : Font,
     prev_input : Typing,
+    /// lines with a breakpoint, toggled with ctrl + b
+    breakpoints : Vec<usize>,
+    /// most lines formatting can leave the code with
+    max_lines : usize,
 }
 
 
@@ -52,6 +60,8 @@ impl<'a> CodeWindow<'a> {
             code_changed : false,
             mono_font,
             prev_input: Typing::new(),
+            breakpoints : Vec::new(),
+            max_lines : usize::MAX,
         }
     }
 /// update code text with user input
@@ -136,10 +146,17 @@ impl<'a> CodeWindow<'a> {
                 //undo
             } else if typing.y {
                 //redo
+            } else if typing.f && !self.prev_input.f {
+                self.format();
+            } else if typing.b && !self.prev_input.b {
+                self.toggle_breakpoint();
             }
             if typing.enter && !self.enter_pressed {
                 self.enter_pressed = true;
                 self.code_changed = true;
+                let at_line_start = self.code_index == 0 || self.code.as_bytes()[self.code_index - 1] == b'\n';
+                let cursor_line = self.cursor_line();
+                breakpoints_line_added(&mut self.breakpoints, cursor_line, at_line_start);
                 self.code.insert(self.code_index, '\n');
                 self.code_index+=1;
             }
@@ -153,6 +170,11 @@ impl<'a> CodeWindow<'a> {
                         self.since_backspace = BACKSPACE_DELAY - BACKSPACE_REPEAT_SPEED;
                     }
                     if self.code_index != 0 {
+                        if self.code.as_bytes()[self.code_index - 1] == b'\n' {
+                            let line_was_empty = self.code_index == 1 || self.code.as_bytes()[self.code_index - 2] == b'\n';
+                            let cursor_line = self.cursor_line();
+                            breakpoints_line_removed(&mut self.breakpoints, cursor_line - 1, line_was_empty);
+                        }
                         self.code.remove(self.code_index - 1);
                         self.code_index-=1;
                     }
@@ -190,6 +212,17 @@ impl<'a> CodeWindow<'a> {
                     );
                 }
             }
+            for line in self.breakpoints.iter() {
+                self.line_draws.push(
+                    font_manager.get_draw_at_vec2(
+                        &self.mono_font,
+                        "*",
+                        TEXT_HEIGHT,
+                        Vec2::new(self.position.x - BREAKPOINT_MARGIN, self.position.y + ((TEXT_HEIGHT + 2) as usize * line) as f64),
+                        BREAKPOINT_COLOUR
+                        )?
+                );
+            }
         }
 
         Ok(())
@@ -204,6 +237,49 @@ impl<'a> CodeWindow<'a> {
         &self.code
     }
 
+    /// lines that have a breakpoint
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    fn cursor_line(&self) -> usize {
+        self.code[..self.code_index].matches('\n').count()
+    }
+
+    /// add or remove a breakpoint on the line of the cursor
+    fn toggle_breakpoint(&mut self) {
+        let cursor_line = self.cursor_line();
+        match self.breakpoints.iter().position(|l| *l == cursor_line) {
+            Some(i) => { self.breakpoints.remove(i); },
+            None => self.breakpoints.push(cursor_line),
+        }
+        self.code_changed = true;
+    }
+
+    /// set the most lines the code can be formatted to, the limit of the microcontroller it's for
+    pub fn set_max_lines(&mut self, max_lines : usize) {
+        self.max_lines = max_lines;
+    }
+
+    /// formats the code, keeping the cursor and breakpoints on the same lines of code.
+    /// code that would have too many lines once formatted is left as it is
+    fn format(&mut self) {
+        let (code, new_lines) = format_code_lines(&self.code);
+        let line_count = code.trim_end().split('\n').count();
+        if line_count > self.max_lines {
+            println!("can't format, it would need {} lines and only {} fit", line_count, self.max_lines);
+            return;
+        }
+        let cursor_line = new_lines[self.cursor_line()];
+        self.breakpoints = self.breakpoints.iter().filter_map(|l| new_lines.get(*l).copied()).collect();
+        self.code = code;
+        self.code_index = match self.code.match_indices('\n').nth(cursor_line) {
+            Some((i, _)) => i,
+            None => self.code.len(),
+        };
+        self.code_changed = true;
+    }
+
     pub fn set_code(&mut self, code : String) {
         self.code = code;
         //updates code
@@ -212,6 +288,25 @@ impl<'a> CodeWindow<'a> {
 }
 
 
+/// moves `breakpoints` for a newline typed on `line`, the lines after it move down.
+/// typed at the start of the line, the line itself moves down as well
+fn breakpoints_line_added(breakpoints : &mut [usize], line : usize, at_line_start : bool) {
+    let first_moved = if at_line_start { line } else { line + 1 };
+    for l in breakpoints.iter_mut().filter(|l| **l >= first_moved) {
+        *l += 1;
+    }
+}
+
+/// moves `breakpoints` for the line after `line` being joined onto the end of it,
+/// a breakpoint on the line merged away is dropped. if `line` was empty it's the one merged away
+fn breakpoints_line_removed(breakpoints : &mut Vec<usize>, line : usize, line_was_empty : bool) {
+    let removed = if line_was_empty { line } else { line + 1 };
+    breakpoints.retain(|l| *l != removed);
+    for l in breakpoints.iter_mut().filter(|l| **l > removed) {
+        *l -= 1;
+    }
+}
+
 fn get_code_lines(code : &str, cursor_index : usize, cursor : char) -> Vec<String> {
     let mut lines : Vec<String> = Vec::new();
     let mut index = 0;
@@ -253,3 +348,30 @@ fn get_next_line_index(code : &str, index : usize) -> usize {
     }
     index
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_follow_added_lines() {
+        let mut breakpoints = vec![1, 3, 5];
+        //newline in the middle of line 3, only the lines after it move
+        breakpoints_line_added(&mut breakpoints, 3, false);
+        assert!(breakpoints == vec![1, 3, 6]);
+        //newline at the start of line 3 moves it down too
+        breakpoints_line_added(&mut breakpoints, 3, true);
+        assert!(breakpoints == vec![1, 4, 7]);
+    }
+
+    #[test]
+    fn breakpoints_follow_removed_lines() {
+        let mut breakpoints = vec![1, 2, 5];
+        //line 2 joined onto line 1, its breakpoint goes with it
+        breakpoints_line_removed(&mut breakpoints, 1, false);
+        assert!(breakpoints == vec![1, 4]);
+        //an empty line 3 removed, line 4 moves up into its place
+        breakpoints_line_removed(&mut breakpoints, 3, true);
+        assert!(breakpoints == vec![1, 3]);
+    }
+}
@@ -1,38 +1,69 @@
 //! Holds a list of microcontrollers and update/draws the currently active one
 
-mod circuit_helper;
 mod circuit_gui;
 mod code_gui;
+mod code_window;
 mod button;
 
 use crate::resource::Font;
-use crate::geometry::Rect;
+use crate::geometry::{Rect, Vec2};
 use crate::input::Typing;
-use crate::{GameObject, FontManager, TextureManager,  microcontroller::Microcontroller};
+use crate::{GameObject, FontManager, TextureManager,  microcontroller::McModel};
+use crate::microcontroller::breakpoint::comment_breakpoints;
+use crate::simulation::{Simulation, SimSnapshot, StopReason, CircuitFile, Tracer, VcdWriter};
 
-use circuit_helper::McConnection;
 use circuit_gui::Gui;
 use code_gui::CodeGui;
+use code_window::CodeWindow;
 
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::path::Path;
-use std::fs::File;
-use std::io::prelude::*;
-use std::str::FromStr;
 
+/// speed of fast forwarding, many steps are done each frame without drawing in between
+const FAST_FORWARD_STEPS_PER_SECOND : f64 = 5000.0;
+/// most steps done in one frame when fast forwarding
+const MAX_FAST_FORWARD_STEPS_PER_FRAME : usize = 500;
+/// steps done each frame when running until halt, as many as can be done without slowing down
+const UNTIL_HALT_STEPS_PER_FRAME : usize = 1000;
+/// most steps done in one frame when running, so a slow frame can't snowball
+const MAX_RUN_STEPS_PER_FRAME : usize = 64;
+/// most steps that can be undone with the step back button
+const HISTORY_SIZE : usize = 512;
+
+/// how the `Simulation` is stepped each frame
+#[derive(Copy, Clone, PartialEq)]
+enum RunMode {
+    Paused,
+    /// at `Gui::steps_per_second`
+    Running,
+    /// at `FAST_FORWARD_STEPS_PER_SECOND`
+    FastForward,
+    /// as fast as possible, pausing once every microcontroller has stopped or the circuit deadlocks
+    UntilHalt,
+}
+
+/// The gui for a `Simulation`, each microcontroller has a `GameObject` and `CodeWindow`
+/// at the same index as it in the `Simulation`
 pub struct Circuit<'a> {
     mc_game_obj : GameObject,
     active_mc : usize,
-    mcs : Vec<Microcontroller<'a>>,
-    connections : HashMap<McConnection, McConnection>,
+    sim : Simulation,
+    mc_objs : Vec<GameObject>,
+    code_windows : Vec<CodeWindow<'a>>,
     mono_font : Font,
     prev_typing : Typing,
     gui : Gui,
     code_gui : CodeGui,
     modified : bool,
+    run_mode : RunMode,
+    /// time since the last step when running
+    step_timer : f64,
+    /// the `Simulation` before each of the last steps, newest at the back.
+    /// cleared when the circuit is changed or compiled, or a step isn't saved
+    history : VecDeque<SimSnapshot>,
 }
 
 impl<'a> Circuit<'a> {
@@ -40,39 +71,38 @@ impl<'a> Circuit<'a> {
         Circuit {
             mc_game_obj,
             active_mc : 0,
-            mcs: Vec::new(),
-            connections : HashMap::new(),
+            sim : Simulation::new(),
+            mc_objs : Vec::new(),
+            code_windows : Vec::new(),
             mono_font : mono_font.clone(),
             prev_typing : Typing::new(),
             gui : Gui::new(btn_game_obj.clone(), mono_font.clone()),
             code_gui : CodeGui::new(btn_game_obj, mono_font),
             modified : true,
+            run_mode : RunMode::Paused,
+            step_timer : 0.0,
+            history : VecDeque::with_capacity(HISTORY_SIZE),
         }
     }
 
-    fn add_circuit(&mut self, rect : Rect) {
+    fn add_circuit(&mut self, rect : Rect, model : &'static McModel) {
         let mut game_obj = self.mc_game_obj.clone();
         game_obj.draw_rect = rect;
-        self.mcs.push(Microcontroller::new(game_obj, self.mono_font.clone()));
-        self.active_mc = self.mcs.len();
-        self.modified = true;
-    }
-
-    fn add_connection(&mut self, con1 : McConnection, con2 : McConnection) -> Result<(), String> {
-        if con1.get_mc_i() >= self.mcs.len() || con2.get_mc_i() >= self.mcs.len() {
-            return Err(String::from("connection: mc index out of range"));
-        }
-        if con1.get_io_i() >= self.mcs[con1.get_mc_i()].io_count() || con2.get_io_i() >= self.mcs[con2.get_mc_i()].io_count() {
-            return Err(String::from("connection: io index out of range"));
-        }
-        self.connections.insert(con1, con2);
+        self.sim.add_mc(model);
+        self.mc_objs.push(game_obj);
+        self.code_windows.push(CodeWindow::new(self.mono_font.clone(), Vec2::new(20.0, 10.0)));
+        self.active_mc = self.sim.mcs().len();
         self.modified = true;
-        Ok(())
+        self.history.clear();
     }
 
     pub fn draw<TTex, TFont>(&mut self, canvas : &mut Canvas<Window>,  texture_manager : &'a TextureManager<TTex>, font_manager : &'a FontManager<TFont>) -> Result<(), String> {
-        if self.active_mc < self.mcs.len() {
-            self.mcs[self.active_mc].draw(canvas, font_manager)?;
+        if self.active_mc < self.code_windows.len() {
+            let code_window = &mut self.code_windows[self.active_mc];
+            code_window.set_draw_lines(font_manager)?;
+            for l in code_window.get_draw_code() {
+                canvas.copy(&l.tex, None, l.rect)?;
+            }
             self.code_gui.draw(canvas, texture_manager, font_manager)?;
         } else {
             self.gui.draw(canvas, texture_manager, font_manager)?;
@@ -82,38 +112,126 @@ impl<'a> Circuit<'a> {
 
     /// update circuit or active `CodeWindow`
     pub fn update(&mut self, frame_elapsed : f64, typing : &mut Typing) {
-        if self.active_mc < self.mcs.len() {
+        if self.active_mc < self.code_windows.len() {
             self.code_controls(frame_elapsed, typing);
         } else {
             self.circuit_controls(typing);
         }
 
+        self.run(frame_elapsed);
+
         self.debug_controls(typing);
        
         self.prev_typing = *typing;
     }
 
+    /// step the `Simulation` for the frame, every mode pauses on a breakpoint or deadlock
+    fn run(&mut self, frame_elapsed : f64) {
+        let steps = match self.run_mode {
+            RunMode::Paused => 0,
+            RunMode::Running => self.timed_steps(frame_elapsed, self.gui.steps_per_second(), MAX_RUN_STEPS_PER_FRAME),
+            RunMode::FastForward => self.timed_steps(frame_elapsed, FAST_FORWARD_STEPS_PER_SECOND, MAX_FAST_FORWARD_STEPS_PER_FRAME),
+            RunMode::UntilHalt => UNTIL_HALT_STEPS_PER_FRAME,
+        };
+        for n in 0..steps {
+            //only the last steps of the frame could be undone, so don't save the ones before
+            if steps - n <= HISTORY_SIZE {
+                self.save_history();
+            } else {
+                self.history.clear();
+            }
+            self.sim.step();
+            match self.sim.stop_reason() {
+                Some(StopReason::Breakpoint) => self.report_breakpoints(),
+                Some(StopReason::Deadlock) => self.report_deadlock(),
+                //running and fast forward keep going once halted
+                Some(StopReason::Halted) if self.run_mode == RunMode::UntilHalt => (),
+                _ => continue,
+            }
+            self.run_mode = RunMode::Paused;
+            break;
+        }
+    }
+
+    /// steps due this frame to keep to `steps_per_second`, at most `max_steps`
+    fn timed_steps(&mut self, frame_elapsed : f64, steps_per_second : f64, max_steps : usize) -> usize {
+        let step_time = 1.0 / steps_per_second;
+        self.step_timer += frame_elapsed;
+        let steps = (self.step_timer / step_time) as usize;
+        self.step_timer -= steps as f64 * step_time;
+        steps.min(max_steps)
+    }
+
+    /// keep the state of the `Simulation` before a step so it can be undone
+    fn save_history(&mut self) {
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.sim.snapshot());
+    }
+
+    /// undo the last step, `false` if there is nothing to undo
+    fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(snapshot) => self.sim.restore(&snapshot).is_ok(),
+            None => false,
+        }
+    }
+
+    fn report_breakpoints(&self) {
+        for (mc, bp) in self.sim.breakpoint_hits() {
+            println!("BREAK: mc {} {}", mc, bp);
+        }
+    }
+
+    /// print the ports each microcontroller is stuck on, if the circuit has deadlocked
+    fn report_deadlock(&self) {
+        if let Some(blocked) = self.sim.deadlock() {
+            println!("DEADLOCK:");
+            for b in blocked {
+                println!("{}", b);
+            }
+        }
+    }
+
     fn circuit_controls(&mut self, typing : &Typing) {
-        self.gui.update(&typing.mouse, &self.mcs, &self.connections, self.modified);
+        self.gui.set_cycles(self.sim.cycles());
+        self.gui.set_tracing(self.sim.tracing());
+        self.gui.set_writing_vcd(self.sim.writing_vcd());
+        let blocked = self.sim.deadlock().unwrap_or_default();
+        self.gui.update(&typing.mouse, self.sim.mcs(), &self.mc_objs, self.sim.connections(), &blocked, self.modified);
         self.modified = false;
-        if let Some(rect) = self.gui.add_circ_request() {
-            self.add_circuit(rect);
+        if let Some((rect, model)) = self.gui.add_circ_request() {
+            self.add_circuit(rect, model);
         }
         //do both way connection
         if let Some((con1, con2)) = self.gui.add_con_request() {
-            if !self.connections.contains_key(&con1) && !self.connections.contains_key(&con2) {
-                self.add_connection(con1, con2).unwrap();
-                self.add_connection(con2, con1).unwrap();
-            }
+            self.sim.connect(con1, con2).unwrap();
+            self.modified = true;
+            self.history.clear();
         }
         if let Some(i) = self.gui.remove_mcs_index() {
-            self.remove_connection(i);
-            self.mcs.swap_remove(i);
+            self.sim.remove_mc(i);
+            self.mc_objs.swap_remove(i);
+            self.code_windows.swap_remove(i);
             self.modified = true;
+            self.history.clear();
         }
         if let Some(i) = self.gui.code_mcs_index() {
             self.active_mc = i;
         }
+        if let Some(i) = self.gui.overflow_mcs_index() {
+            let mc = self.sim.mc_mut(i);
+            mc.set_overflow_mode(mc.overflow_mode().next());
+            println!("mc {} overflow mode {}, compile for it to take effect", i, mc.overflow_mode().name());
+        }
+        if let Some(i) = self.gui.step_mcs_index() {
+            self.run_mode = RunMode::Paused;
+            self.save_history();
+            self.sim.step_mc(i);
+            self.sim.mcs()[i].debug_print_registers();
+            self.report_breakpoints();
+        }
 
         if self.gui.save_circuit() {
             self.save_to_file(Path::new("saves/test.circ")).unwrap();
@@ -125,186 +243,182 @@ impl<'a> Circuit<'a> {
         }
 
         if self.gui.clear_circuit() {
-            self.connections.clear();
-            self.mcs.clear();
-            self.modified = true;
+            self.clear();
+        }
+
+        if self.gui.toggle_trace() {
+            self.toggle_trace(Path::new("saves/trace.jsonl"));
+        }
+
+        if self.gui.toggle_vcd() {
+            self.toggle_vcd(Path::new("saves/ports.vcd"));
         }
 
         if self.gui.compile() {
-            for mc in self.mcs.as_mut_slice() {
-                match mc.compile() {
-                    Ok(_) => println!("Code OK"),
-                    Err(e) => println!("ERR:\n{:?}\n", e),
+            self.history.clear();
+            self.set_comment_breakpoints();
+            for result in self.sim.compile() {
+                match result {
+                    Ok(warnings) => {
+                        println!("Code OK");
+                        for w in warnings {
+                            println!("{}", w);
+                        }
+                    },
+                    Err(errors) => {
+                        println!("ERR:");
+                        for e in errors {
+                            println!("{}", e);
+                        }
+                        println!();
+                    },
                 }
             }
         }
 
+        if self.gui.run() {
+            self.run_mode = RunMode::Running;
+            self.step_timer = 0.0;
+        }
+        if self.gui.fast_forward() {
+            self.run_mode = RunMode::FastForward;
+            self.step_timer = 0.0;
+        }
+        if self.gui.run_until_halt() {
+            self.run_mode = RunMode::UntilHalt;
+        }
+        if self.gui.pause() {
+            self.run_mode = RunMode::Paused;
+        }
+
+        if self.gui.step_back() {
+            self.run_mode = RunMode::Paused;
+            if !self.step_back() {
+                println!("nothing to step back to");
+            }
+        }
+
         if self.gui.step() {
-            self.step_circuit();
+            self.run_mode = RunMode::Paused;
+            self.save_history();
+            self.sim.step();
+            for mc in self.sim.mcs() {
+                mc.debug_print_registers();
+            }
+            self.report_breakpoints();
+            self.report_deadlock();
+        }
+    }
+
+    /// set the conditional breakpoints written as `; break` comments in the code of each microcontroller
+    fn set_comment_breakpoints(&mut self) {
+        for i in 0..self.sim.mcs().len() {
+            let mc = self.sim.mc_mut(i);
+            match comment_breakpoints(mc.get_code()) {
+                Ok(conditions) => {
+                    for bp in conditions.iter() {
+                        println!("mc {} breakpoint {}", i, bp);
+                    }
+                    mc.set_condition_breakpoints(&conditions);
+                },
+                Err(errors) => {
+                    println!("BREAKPOINT ERR: mc {}", i);
+                    for e in errors {
+                        println!("{}", e);
+                    }
+                },
+            }
         }
     }
 
     fn code_controls(&mut self, frame_elapsed : f64, typing : &mut Typing) {
-        self.mcs[self.active_mc].update(frame_elapsed, typing);
+        let code_window = &mut self.code_windows[self.active_mc];
+        let mc = self.sim.mc_mut(self.active_mc);
+        code_window.set_max_lines(mc.model().max_source_lines);
+        code_window.update(frame_elapsed, typing);
+        if mc.get_code() != code_window.get_code() {
+            mc.set_code(code_window.get_code().to_string());
+        }
+        mc.set_line_breakpoints(code_window.breakpoints());
         self.code_gui.update(&typing.mouse);
 
         if self.code_gui.circuit_btn() {
-            self.active_mc = self.mcs.len();
+            self.active_mc = self.code_windows.len();
         }
     }
 
-    fn remove_connection(&mut self, i : usize) {
-        if self.mcs.len() == 0 { return (); }
-        let mut to_remove : Vec<McConnection> = Vec::new();
-        let mut change_keys : Vec<McConnection> = Vec::new();
-        let changed_index = self.mcs.len() - 1;
-        
-        for con in self.connections.iter() {
-            if con.0.get_mc_i() == i || con.1.get_mc_i() == i {
-                    to_remove.push(*con.0);
-            }
-            else if changed_index != i {
-                if con.0.get_mc_i() == changed_index || con.1.get_mc_i() == changed_index{
-                    change_keys.push(*con.0);
-                }
-            }
-        }
-        for r in to_remove {
-            self.connections.remove(&r);
-            }
-        for k in change_keys {
-            let mut v : McConnection = *self.connections.get(&k).unwrap();
-            let mut new_k : McConnection = k;
-            if new_k.get_mc_i() == changed_index {
-                new_k = McConnection::new(i, new_k.get_io_i());
-            }
-            if v.get_mc_i() == changed_index {
-                v = McConnection::new(i, v.get_io_i());
-            }
-            self.connections.remove(&k);
-            self.connections.insert(new_k, v);
-        }
-    }
-    
-    fn io_in_ready(&self, connection : &McConnection) -> bool {
-        self.mcs[connection.get_mc_i()].io_read_in_ready(connection.get_io_i())
+    fn clear(&mut self) {
+        self.sim.clear();
+        self.mc_objs.clear();
+        self.code_windows.clear();
+        self.active_mc = 0;
+        self.modified = true;
+        self.history.clear();
     }
 
-    fn io_out_ready(&self, connection : &McConnection) -> bool {
-        self.mcs[connection.get_mc_i()].io_read_out_ready(connection.get_io_i())
+    /// start writing a trace of every step to the file, or stop if one is being written
+    fn toggle_trace(&mut self, path : &Path) {
+        match self.sim.take_tracer() {
+            Some(tracer) => match tracer.finish() {
+                Ok(()) => println!("trace written to {}", path.display()),
+                Err(e) => println!("error writing trace: {}", e),
+            },
+            None => match Tracer::create(path) {
+                Ok(tracer) => self.sim.set_tracer(tracer),
+                Err(e) => println!("error creating trace: {}", e),
+            },
+        }
     }
 
-    fn step_circuit(&mut self) {
-        let mut read_out_ports : Vec<McConnection> = Vec::new();
-        for (mc_i, mc) in self.mcs.as_mut_slice().into_iter().enumerate() {
-            mc.step();
-            mc.debug_print_registers();
-            for port_i in 0..mc.io_count() {
-                if mc.io_read_out_ready(port_i) {
-                    read_out_ports.push(McConnection::new(mc_i, port_i));
-                }
-            }
-        }
-        //read out until no more read_outs left
-        loop {
-            let mut read_out_val = false;
-            for io_out in read_out_ports.as_slice().into_iter() {
-                match self.connections.get(&io_out) {
-                    Some(io_in) => {
-                        if self.io_in_ready(io_in) && self.io_out_ready(io_out) {
-                            let value = self.mcs[io_out.get_mc_i()].io_read_out(io_out.get_io_i()).unwrap();
-                            self.mcs[io_in.get_mc_i()].io_read_in(value, io_in.get_io_i()).unwrap();
-                            //step for read in mc to complete instruction
-                            self.mcs[io_in.get_mc_i()].step();
-                            read_out_val = true;
-                        }
-                    },
-                    None => (),
-                }
-            }
-            if !read_out_val {
-                break;
-            }
+    /// start writing the io ports of every step to a VCD file, or stop if one is being written
+    fn toggle_vcd(&mut self, path : &Path) {
+        match self.sim.take_vcd() {
+            Some(vcd) => match vcd.finish() {
+                Ok(()) => println!("VCD written to {}", path.display()),
+                Err(e) => println!("error writing VCD: {}", e),
+            },
+            None => match VcdWriter::create(path) {
+                Ok(vcd) => self.sim.set_vcd(vcd),
+                Err(e) => println!("error creating VCD: {}", e),
+            },
         }
     }
 
     /// save the circuit to given file path
     pub fn save_to_file(&self, path : &Path) -> Result<(), String> {
-        let mut file = File::create(path).map_err(|e| e.to_string())?;
-        //save mcs
-        for mc in self.mcs.as_slice() {
-            let mc_game_obj = mc.get_game_object();
-            file.write(
-                format!(
-                    "<mc>\n{} {} {} {}\n",
-                    mc_game_obj.draw_rect.x,
-                    mc_game_obj.draw_rect.y,
-                    mc_game_obj.draw_rect.w,
-                    mc_game_obj.draw_rect.h,
-                ).as_bytes()
-            ).map_err(|e| e.to_string())?;
-            file.write(mc.get_code().as_bytes()).map_err(|e| e.to_string())?;
-            file.write("\n".as_bytes()).map_err(|e| e.to_string())?;
-        }
-        //save mc connections
-        file.write("<connections>\n".as_bytes()).map_err(|e| e.to_string())?;
-        for (out_io, in_io) in self.connections.iter() {
-             file.write(
-                format!(
-                    "{} {} {} {}\n",
-                    out_io.get_mc_i(),
-                    out_io.get_io_i(),
-                    in_io.get_mc_i(),
-                    in_io.get_io_i(),
-                ).as_bytes()
-            ).map_err(|e| e.to_string())?;
-        }
-
-        Ok(())
+        let rects : Vec<Rect> = self.mc_objs.iter().map(|obj| obj.draw_rect.clone()).collect();
+        CircuitFile::new(&self.sim, &rects).save(path)
     }
 
     /// clear current circuit and load previously saved circuit
     pub fn load_from_file(&mut self, path : &Path) -> Result<(), String> {
-        //clear circuit
-        self.mcs.clear();
-        self.connections.clear();
-        self.active_mc = 0;
-
-        let mut file = File::open(path).map_err(|e| e.to_string())?;
-        let mut text = String::new();
-        file.read_to_string(&mut text).map_err(|e| e.to_string())?;
-        let (mc_text, connection_text) = match text.split_once("<connections>") {
-            Some(v) => v,
-            None => { return Err(String::from("error parsing <connections>")); },
-        };
-        //load mcs
-        for mc in mc_text.split("<mc>").skip(1) {
-            //get rect
-            let (rect, code) = match mc.trim_start().split_once("\n") {
-                Some(v) => v,
-                None => { return Err(String::from("error parsing rect/code split")); },
-            };
-            let rect = parse_4_vals(rect.trim())?;
-            let rect = Rect::new(rect[0], rect[1], rect[2], rect[3]);
-            self.add_circuit(rect);
-            self.mcs.last_mut().unwrap().set_code(code.to_string());
-        }
-
-        //load mc connections
-        for con in connection_text.trim().split("\n") {
-            if con != "" {
-                let con = parse_4_vals(con)?;
-                self.add_connection(McConnection::new(con[0], con[1]), McConnection::new(con[2], con[3]))?;
+        let file = CircuitFile::load(path)?;
+        //the dump only has the ports of the old circuit, so it ends here
+        if let Some(vcd) = self.sim.take_vcd() {
+            match vcd.finish() {
+                Ok(()) => println!("VCD finished, the loaded circuit isn't in it"),
+                Err(e) => println!("error writing VCD: {}", e),
             }
         }
+        self.clear();
+        for saved in file.mcs.iter() {
+            self.add_circuit(saved.rect.clone(), saved.model);
+            self.code_windows.last_mut().unwrap().set_code(saved.code.clone());
+        }
+        let tracer = self.sim.take_tracer();
+        self.sim = file.to_simulation()?;
+        if let Some(tracer) = tracer {
+            self.sim.set_tracer(tracer);
+        }
         self.modified = true;
+        self.history.clear();
         Ok(())
     }
 
     fn debug_controls(&mut self, typing : &mut Typing) {
         if typing.ctrl && typing.z && !self.prev_typing.z {
             println!("printing connections: ");
-            for con in self.connections.iter() {
+            for con in self.sim.connections().iter() {
                 println!("mc: {}, io: {} -> mc: {}, io: {}",
                          con.0.get_mc_i(), con.0.get_io_i(),
                          con.1.get_mc_i(), con.1.get_io_i()
@@ -313,23 +427,3 @@ impl<'a> Circuit<'a> {
         }
     }
 }
-
-fn parse_4_vals<T : FromStr>(text : &str) -> Result<Vec::<T>, String> 
-where <T as FromStr>::Err : std::fmt::Debug {
-    let mut vals : Vec<T> = Vec::new();
-    let vals_result : Vec<Result<T, String>> =
-        text
-        .split(" ")
-        .map(
-            |v|
-            v.trim().parse::<T>().map_err(|_| String::from(format!("error parsing str into number [circuit::parse_4_vals()], text : {}", v.trim())))
-        )
-        .collect();
-    for v in vals_result {
-        vals.push(v?)
-    }
-    if vals.len() != 4 {
-        return Err(String::from("parse wasn't 4"));
-    }
-    Ok(vals)
-}
@@ -1,5 +1,6 @@
 //! Shape structs with `f64` members
 
+#[cfg(feature = "gui")]
 use sdl2::rect;
 
 use std::ops;
@@ -40,6 +41,7 @@ impl Rect {
     }
     
     /// Use an `sdl2::rect::Rect` to construct a `Rect`
+    #[cfg(feature = "gui")]
     pub fn new_from_sdl_rect(sdl_rect : &rect::Rect) -> Self {
         Rect {
             x: sdl_rect.x as f64,
@@ -49,6 +51,7 @@ impl Rect {
         }
     }
     /// construct an `sdl2::rect::Rect` using this `Rect`
+    #[cfg(feature = "gui")]
     pub fn to_sdl_rect(&self) -> rect::Rect {
         rect::Rect::new(self.x as i32, self.y as i32, self.w as u32, self.h as u32)
     }
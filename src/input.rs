@@ -42,6 +42,8 @@ pub struct Typing {
     pub l         : bool,
     pub n         : bool,
     pub p         : bool,
+    pub f         : bool,
+    pub b         : bool,
     pub up        : bool,
     pub down      : bool,
     pub left      : bool,
@@ -67,6 +69,8 @@ impl Typing {
             l        : false,
             n        : false,
             p        : false,
+            f        : false,
+            b        : false,
             up       : false,
             down     : false,
             left     : false,
@@ -125,6 +129,8 @@ impl Typing {
                     Scancode::L => self.l = key_down,
                     Scancode::N => self.n = key_down,
                     Scancode::P => self.p = key_down,
+                    Scancode::F => self.f = key_down,
+                    Scancode::B => self.b = key_down,
                     _ => {}
                 }
             }
@@ -1,29 +1,46 @@
 //! Parses code into a list of `Instructions` and `Operand`s
 //! for a `Program` to execute
 
+use super::macros::{expand_macros, SourceLine};
+use super::model::McModel;
+
 use std::collections::HashMap;
+use std::fmt;
 
 /// Amount of IO registers a `Program` has
 pub const IO_REGISTER_COUNT : usize = 4;
 
 /// Tells the program what to do with the `Operand`s
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instruction {
     ADD,
     SUB,
     MUL,
     DIV,
+    MOD,
+    AND,
+    OR,
+    XOR,
+    NOT,
+    SHL,
+    SHR,
     CMP,
     BRC,
     BEQ,
     BGT,
     BLT,
+    JSR,
+    RET,
+    PUSH,
+    POP,
+    LD,
+    ST,
     NOP,
     HLT,
 }
 
 /// represents an `i16` member of `Program`
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Register {
     PC,
     R1,
@@ -32,29 +49,214 @@ pub enum Register {
     RIO(usize),
 }
 
+impl fmt::Display for Register {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::RIO(n) => write!(f, "IO{}", n),
+            reg => write!(f, "{:?}", reg),
+        }
+    }
+}
+
+/// a location in the data memory of a `Program`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Address {
+    Direct(i16),
+    Reg(Register),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Direct(n) => write!(f, "[#{}]", n),
+            Address::Reg(reg) => write!(f, "[{}]", reg),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum InterimOp {
     Reg(Register),
     Direct(i16),
     Lable(String),
+    Mem(Address),
+}
+
+/// Points to some code, `start` and `end` are the columns of the first
+/// and one past the last character on the line at index `line`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line  : usize,
+    pub start : usize,
+    pub end   : usize,
+}
+
+impl Span {
+    pub fn new(line : usize, start : usize, end : usize) -> Self {
+        Span { line, start, end }
+    }
+}
+
+/// Indicates what caused the assembler to fail
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    TooManyOps,
+    UnknownOp,
+    UnknownInst,
+    MissingLable,
+    UnknownLable(String),
+    MisformedLable,
+    UnknownNumber,
+    TooManySpaces,
+    JumpNeedsLable,
+    InstAfterLable,
+    TooFewOps,
+    InvalidOp,
+    OutOfRangeIO,
+    DuplicateLable(String),
+    NumberOutOfRange,
+    UnknownDirective,
+    InvalidName,
+    DuplicateName(String),
+    NestedMacro,
+    UnmatchedEndm,
+    UnterminatedMacro,
+    WrongArgCount(usize),
+    RecursiveMacro(String),
+    MacroTooLong(usize),
+    TooManyLines(usize),
+    TooManyInstructions(usize),
+    MissingRegister(Register),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::TooManyOps => write!(f, "too many operands"),
+            ErrorKind::UnknownOp => write!(f, "unknown operand"),
+            ErrorKind::UnknownInst => write!(f, "unknown instruction"),
+            ErrorKind::MissingLable => write!(f, "expected a lable"),
+            ErrorKind::UnknownLable(lable) => write!(f, "lable \"{}\" is not defined", lable),
+            ErrorKind::MisformedLable => write!(f, "misformed lable"),
+            ErrorKind::UnknownNumber => write!(f, "not a number"),
+            ErrorKind::TooManySpaces => write!(f, "too many spaces"),
+            ErrorKind::JumpNeedsLable => write!(f, "jump needs a lable"),
+            ErrorKind::InstAfterLable => write!(f, "expected an instruction after the lable"),
+            ErrorKind::TooFewOps => write!(f, "too few operands"),
+            ErrorKind::InvalidOp => write!(f, "operand can't be used here"),
+            ErrorKind::OutOfRangeIO => write!(f, "io register out of range"),
+            ErrorKind::DuplicateLable(lable) => write!(f, "lable \"{}\" is already defined", lable),
+            ErrorKind::NumberOutOfRange => write!(f, "number doesn't fit in a register ({} to {})", i16::MIN, i16::MAX),
+            ErrorKind::UnknownDirective => write!(f, "unknown directive"),
+            ErrorKind::InvalidName => write!(f, "name can't be a number, register or instruction"),
+            ErrorKind::DuplicateName(name) => write!(f, "\"{}\" is already defined", name),
+            ErrorKind::NestedMacro => write!(f, "can't define a macro inside a macro"),
+            ErrorKind::UnmatchedEndm => write!(f, ".endm without a .macro"),
+            ErrorKind::UnterminatedMacro => write!(f, "macro has no .endm"),
+            ErrorKind::WrongArgCount(n) => write!(f, "macro takes {} arguments", n),
+            ErrorKind::RecursiveMacro(name) => write!(f, "macro \"{}\" is used inside itself", name),
+            ErrorKind::MacroTooLong(max) => write!(f, "macros expand to more than {} lines", max),
+            ErrorKind::TooManyLines(max) => write!(f, "code is longer than the {} lines this model allows", max),
+            ErrorKind::TooManyInstructions(max) => write!(f, "more than the {} instructions this model allows", max),
+            ErrorKind::MissingRegister(reg) => write!(f, "this model doesn't have register {}", reg),
+        }
+    }
+}
+
+/// An `ErrorKind` and the `Span` of code that caused it,
+/// if the code is in a macro `expanded_from` is where the macro was used
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeError {
+    pub kind : ErrorKind,
+    pub span : Span,
+    pub expanded_from : Option<Span>,
+}
+
+impl CodeError {
+    pub fn new(kind : ErrorKind, span : Span) -> Self {
+        CodeError { kind, span, expanded_from : None }
+    }
+
+    pub fn expanded_from(mut self, expanded_from : Option<Span>) -> Self {
+        self.expanded_from = expanded_from;
+        self
+    }
+}
+
+fn fmt_expanded_from(f : &mut fmt::Formatter, expanded_from : Option<Span>) -> fmt::Result {
+    match expanded_from {
+        Some(span) => write!(f, " (in macro used on line {})", span.line + 1),
+        None => Ok(()),
+    }
 }
 
-/// Indicates what caused the assembler to fail and returns a `usize`
-/// that points to the offending line of code
+/// line and columns are shown counting from 1
+impl fmt::Display for CodeError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, col {}-{}: {}", self.span.line + 1, self.span.start + 1, self.span.end, self.kind)?;
+        fmt_expanded_from(f, self.expanded_from)
+    }
+}
+
+/// Something suspicious in code that can still be assembled
+#[derive(Clone, Debug, PartialEq)]
+pub enum WarningKind {
+    UnusedLable(String),
+    Unreachable,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::UnusedLable(lable) => write!(f, "lable \"{}\" is never used", lable),
+            WarningKind::Unreachable => write!(f, "code can never be reached"),
+        }
+    }
+}
+
+/// A `WarningKind` and the `Span` of code that caused it
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeWarning {
+    pub kind : WarningKind,
+    pub span : Span,
+    pub expanded_from : Option<Span>,
+}
+
+impl CodeWarning {
+    pub fn new(kind : WarningKind, span : Span) -> Self {
+        CodeWarning { kind, span, expanded_from : None }
+    }
+}
+
+impl fmt::Display for CodeWarning {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, col {}-{}: warning: {}", self.span.line + 1, self.span.start + 1, self.span.end, self.kind)?;
+        fmt_expanded_from(f, self.expanded_from)
+    }
+}
+
+/// The result of assembling code, the `Line`s to execute and any warnings about the code
 #[derive(Debug)]
-pub enum CodeError {
-    TooManyOps(usize),
-    UnknownOp(usize),
-    UnknownInst(usize),
-    MissingLable(usize),
-    MisformedLable(usize),
-    UnknownNumber(usize),
-    TooManySpaces(usize),
-    JumpNeedsLable(usize),
-    InstAfterLable(usize),
-    TooFewOps(usize),
-    InvalidOp(usize),
-    OutOfRangeIO(usize),
+pub struct Assembly {
+    pub lines : Vec<Line>,
+    pub warnings : Vec<CodeWarning>,
+    /// index of the source line each `Line` came from, a line from a macro
+    /// is from the line in the `.macro` body
+    pub source_lines : Vec<usize>,
+}
+
+/// A name defined by a `.equ` or `.alias` directive
+#[derive(Copy, Clone, Debug)]
+enum Symbol {
+    Const(i16),
+    Alias(Register),
+}
+
+/// A word of code and where it is in the source
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub text : String,
+    pub span : Span,
 }
 
 #[derive(Debug)]
@@ -64,6 +266,10 @@ struct InterimLine {
     op1   : Option<InterimOp>,
     op2   : Option<InterimOp>,
     op3   : Option<InterimOp>,
+    lable_span : Span,
+    instr_span : Span,
+    op_spans   : [Span ; 3],
+    expanded_from : Option<Span>,
 }
 
 impl InterimLine {
@@ -74,19 +280,55 @@ impl InterimLine {
             op1 : None,
             op2 : None,
             op3 : None,
+            lable_span : Span::default(),
+            instr_span : Span::default(),
+            op_spans : [Span::default() ; 3],
+            expanded_from : None,
+        }
+    }
+
+    /// the source line of the instruction, or the lable if it has no instruction of its own
+    fn source_line(&self) -> usize {
+        self.span().line
+    }
+
+    /// the `Span` of the instruction, or the lable if it has no instruction of its own
+    fn span(&self) -> Span {
+        if self.instr_span == Span::default() && self.lable.is_some() {
+            self.lable_span
+        } else {
+            self.instr_span
         }
     }
+
+    fn error(&self, kind : ErrorKind, op_index : Option<usize>) -> CodeError {
+        CodeError::new(kind, match op_index {
+            Some(i) => self.op_spans[i],
+            None => self.instr_span,
+        }).expanded_from(self.expanded_from)
+    }
 }
 
-/// Either a `Register` or an `i16` value
-#[derive(Copy, Clone)]
+/// Either a `Register`, an `i16` value or an `Address` in data memory
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Operand {
     Reg(Register),
-    Direct(i16)
+    Direct(i16),
+    Mem(Address),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Direct(n) => write!(f, "#{}", n),
+            Operand::Mem(address) => write!(f, "{}", address),
+        }
+    }
 }
 
 /// An `Instruction` with 0 to 3 `Operand`s
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Line {
     pub instr : Instruction,
     pub op1   : Option<Operand>,
@@ -94,8 +336,108 @@ pub struct Line {
     pub op3   : Option<Operand>,
 }
 
+impl fmt::Display for Line {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.instr)?;
+        for op in [self.op1, self.op2, self.op3].iter().flatten() {
+            write!(f, " {}", op)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a line of code into `Token`s, ignoring anything after a `;`
+/// that isn't inside a character literal
+pub fn tokenise(line : &str, line_index : usize) -> Vec<Token> {
+    tokenise_with_comment(line, line_index).0
+}
+
+/// same as `tokenise`, also returns the index of the `;` starting a comment
+pub fn tokenise_with_comment(line : &str, line_index : usize) -> (Vec<Token>, Option<usize>) {
+    let mut tokens = Vec::new();
+    let mut comment = None;
+    let mut start = None;
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut end = line.len();
+    for (i, c) in line.char_indices() {
+        if in_quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '\'' {
+                in_quote = false;
+            }
+            continue;
+        }
+        if c == ';' {
+            end = i;
+            comment = Some(i);
+            break;
+        }
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                tokens.push(Token { text : line[s..i].to_string(), span : Span::new(line_index, s, i) });
+                start = None;
+            },
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+        if c == '\'' {
+            in_quote = true;
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(Token { text : line[s..end].to_string(), span : Span::new(line_index, s, end) });
+    }
+    (tokens, comment)
+}
+
+/// Parses a number literal without the `#`, accepts decimal, hex with `0x`,
+/// binary with `0b`, an optional `-` sign and ascii characters in single quotes
+pub fn parse_number(text : &str) -> Result<i16, ErrorKind> {
+    if let Some(c) = text.strip_prefix('\'') {
+        let c = c.strip_suffix('\'').ok_or(ErrorKind::UnknownNumber)?;
+        let value = match c {
+            "\\n" => '\n',
+            "\\t" => '\t',
+            "\\0" => '\0',
+            "\\\\" => '\\',
+            "\\'" => '\'',
+            _ => {
+                let mut chars = c.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii() => c,
+                    _ => return Err(ErrorKind::UnknownNumber),
+                }
+            }
+        };
+        return Ok(value as i16);
+    }
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, text),
+    };
+    let lower = digits.to_lowercase();
+    let (radix, digits) = if let Some(d) = lower.strip_prefix("0x") {
+        (16, d)
+    } else if let Some(d) = lower.strip_prefix("0b") {
+        (2, d)
+    } else {
+        (10, lower.as_str())
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(ErrorKind::UnknownNumber);
+    }
+    let value = match i64::from_str_radix(digits, radix) {
+        Ok(v) => if negative { -v } else { v },
+        Err(_) => return Err(ErrorKind::NumberOutOfRange),
+    };
+    i16::try_from(value).map_err(|_| ErrorKind::NumberOutOfRange)
+}
 
-fn get_operand(word : &str, line_index : usize) -> Result<InterimOp, CodeError> {
+fn get_operand(word : &str, span : Span, symbols : &HashMap<String, Symbol>) -> Result<InterimOp, CodeError> {
     Ok(
         {
             let word = word.trim();
@@ -105,125 +447,276 @@ fn get_operand(word : &str, line_index : usize) -> Result<InterimOp, CodeError>
                 "R2" => InterimOp::Reg(Register::R2),
                 "RT" => InterimOp::Reg(Register::RT),
                 _ => {
-                    if word.to_uppercase().starts_with("IO") {
+                    if word.len() > 2 && word.starts_with('[') && word.ends_with(']') {
+                        let inner_span = Span::new(span.line, span.start + 1, span.end - 1);
+                        match get_operand(&word[1..word.len() - 1], inner_span, symbols)? {
+                            InterimOp::Direct(n) => InterimOp::Mem(Address::Direct(n)),
+                            InterimOp::Reg(Register::RIO(_)) => return Err(CodeError::new(ErrorKind::InvalidOp, span)),
+                            InterimOp::Reg(reg) => InterimOp::Mem(Address::Reg(reg)),
+                            _ => return Err(CodeError::new(ErrorKind::InvalidOp, span)),
+                        }
+                    }
+                    else if word.to_uppercase().starts_with("IO") {
                         match word.split_at(2).1.parse::<u16>() {
                             Ok(n) => {
                                 if n as usize >= IO_REGISTER_COUNT {
-                                    return Err(CodeError::OutOfRangeIO(line_index));
+                                    return Err(CodeError::new(ErrorKind::OutOfRangeIO, span));
                                 }
                                 InterimOp::Reg(Register::RIO(n as usize))
                             },
-                            _ => { return Err(CodeError::UnknownNumber(line_index));
+                            _ => { return Err(CodeError::new(ErrorKind::UnknownNumber, span));
                             }
                         }
                     }
                     else if word.starts_with("#") {
-                        match word.split_at(1).1.parse::<u16>() {
-                            Ok(n) => InterimOp::Direct(n as i16),
-                            _ => { return Err(CodeError::UnknownNumber(line_index)); }
+                        match parse_number(word.split_at(1).1) {
+                            Ok(n) => InterimOp::Direct(n),
+                            Err(kind) => { return Err(CodeError::new(kind, span)); }
                         }
-                    } else { InterimOp::Lable(word.to_string()) }
+                    } else {
+                        match symbols.get(word) {
+                            Some(Symbol::Const(n)) => InterimOp::Direct(*n),
+                            Some(Symbol::Alias(reg)) => InterimOp::Reg(*reg),
+                            None => InterimOp::Lable(word.to_string()),
+                        }
+                    }
                 }
             }
         }
     )
 }
 
-fn get_instruction(text: &str) -> Result<Instruction, ()> {
+pub(super) fn get_instruction(text: &str) -> Result<Instruction, ()> {
     let text = text.trim().to_uppercase();
     match text.to_uppercase().as_str() {
                             "ADD" => Ok(Instruction::ADD),
                             "SUB" => Ok(Instruction::SUB),
                             "MUL" => Ok(Instruction::MUL),
                             "DIV" => Ok(Instruction::DIV),
+                            "MOD" => Ok(Instruction::MOD),
+                            "AND" => Ok(Instruction::AND),
+                            "OR"  => Ok(Instruction::OR),
+                            "XOR" => Ok(Instruction::XOR),
+                            "NOT" => Ok(Instruction::NOT),
+                            "SHL" => Ok(Instruction::SHL),
+                            "SHR" => Ok(Instruction::SHR),
                             "CMP" => Ok(Instruction::CMP),
                             "BRC" => Ok(Instruction::BRC),
                             "BEQ" => Ok(Instruction::BEQ),
                             "BGT" => Ok(Instruction::BGT),
                             "BLT" => Ok(Instruction::BLT),
+                            "JSR" => Ok(Instruction::JSR),
+                            "RET" => Ok(Instruction::RET),
+                            "PUSH" => Ok(Instruction::PUSH),
+                            "POP" => Ok(Instruction::POP),
+                            "LD"  => Ok(Instruction::LD),
+                            "ST"  => Ok(Instruction::ST),
                             "NOP" => Ok(Instruction::NOP),
                             "HLT" => Ok(Instruction::HLT),
                             _     => Err(())
     }
 }
 
-fn check_line(line : &InterimLine, line_index : usize) -> Result<(), CodeError> {
-    if line.instr.is_none()  {
-        if line.op1.is_some() || line.op2.is_some() || line.op3.is_some() {
-            Err(CodeError::InstAfterLable(line_index))
-        } else {  Ok(()) }
-    } else {
-        match line.instr.unwrap() {
-            Instruction::HLT |
-            Instruction::NOP => if line.op1.is_some() || line.op2.is_some() || line.op3.is_some() {
-                                    Err(CodeError::TooManyOps(line_index))
-                                } else {
-                                    Ok(())
-                                }
-            Instruction::BRC |
-            Instruction::BEQ |
-            Instruction::BGT |
-            Instruction::BLT => {
-                match &line.op1 {
-                    Some(op) => {
-                        match op {
-                            InterimOp::Lable(..) =>
-                                if  line.op2.is_some() || line.op3.is_some() {
-                                    Err(CodeError::TooManyOps(line_index))
-                                } else {
-                                    Ok(())
-                                },
-                            _ => Err(CodeError::MissingLable(line_index))
-                        }
-                    },
-                    _ => Err(CodeError::MissingLable(line_index))
-                }
+fn check_line(line : &InterimLine) -> Result<(), CodeError> {
+    let ops = [&line.op1, &line.op2, &line.op3];
+    let Some(instr) = line.instr else {
+        return if ops.iter().any(|op| op.is_some()) {
+            Err(line.error(ErrorKind::InstAfterLable, None))
+        } else { Ok(()) };
+    };
+    match instr {
+        Instruction::LD |
+        Instruction::ST => (),
+        _ => if let Some(i) = ops.iter().position(|op| matches!(op, Some(InterimOp::Mem(..)))) {
+            return Err(line.error(ErrorKind::InvalidOp, Some(i)));
+        }
+    }
+    match instr {
+        Instruction::HLT |
+        Instruction::RET |
+        Instruction::NOP => if line.op1.is_some() {
+                                Err(line.error(ErrorKind::TooManyOps, Some(0)))
+                            } else {
+                                Ok(())
+                            }
+        Instruction::BRC |
+        Instruction::BEQ |
+        Instruction::BGT |
+        Instruction::BLT |
+        Instruction::JSR => {
+            match &line.op1 {
+                Some(op) => {
+                    match op {
+                        InterimOp::Lable(..) =>
+                            if  line.op2.is_some() {
+                                Err(line.error(ErrorKind::TooManyOps, Some(1)))
+                            } else {
+                                Ok(())
+                            },
+                        _ => Err(line.error(ErrorKind::MissingLable, Some(0)))
+                    }
+                },
+                _ => Err(line.error(ErrorKind::MissingLable, None))
             }
-            Instruction::CMP => {
-                        if line.op1.is_none() || line.op2.is_none() {
-                            return Err(CodeError::TooFewOps(line_index));
-                        }               
-                        if line.op3.is_some() {
-                            return Err(CodeError::TooManyOps(line_index));
-                        }
-                        Ok(())
-                    },
-            Instruction::ADD |
-            Instruction::SUB |
-            Instruction::MUL |
-            Instruction::DIV => {
-                if line.op1.is_none() || line.op2.is_none()  {
-                    return Err(CodeError::TooFewOps(line_index));
-                }
-                if line.op3.is_some()  {
-                    match line.op3.as_ref().unwrap() {
-                        InterimOp::Lable(..) |
-                        InterimOp::Direct(..) => Err(CodeError::InvalidOp(line_index)),
-                        _ => Ok(()),
+        }
+        Instruction::CMP => {
+                    if line.op1.is_none() || line.op2.is_none() {
+                        return Err(line.error(ErrorKind::TooFewOps, None));
                     }
-                } else { Err(CodeError::TooFewOps(line_index)) }
+                    if line.op3.is_some() {
+                        return Err(line.error(ErrorKind::TooManyOps, Some(2)));
+                    }
+                    Ok(())
+                },
+        Instruction::PUSH |
+        Instruction::POP => {
+            let Some(op1) = &line.op1 else {
+                return Err(line.error(ErrorKind::TooFewOps, None));
+            };
+            if line.op2.is_some() {
+                return Err(line.error(ErrorKind::TooManyOps, Some(1)));
+            }
+            match (instr, op1) {
+                (Instruction::POP, InterimOp::Lable(..)) |
+                (Instruction::POP, InterimOp::Direct(..)) => Err(line.error(ErrorKind::InvalidOp, Some(0))),
+                _ => Ok(()),
+            }
+        },
+        Instruction::LD |
+        Instruction::ST => {
+            let (Some(op1), Some(op2)) = (&line.op1, &line.op2) else {
+                return Err(line.error(ErrorKind::TooFewOps, None));
+            };
+            if line.op3.is_some() {
+                return Err(line.error(ErrorKind::TooManyOps, Some(2)));
             }
+            match (instr, op1, op2) {
+                (Instruction::LD, InterimOp::Mem(..), InterimOp::Reg(..)) => Ok(()),
+                (Instruction::LD, InterimOp::Mem(..), _) => Err(line.error(ErrorKind::InvalidOp, Some(1))),
+                (Instruction::LD, _, _) => Err(line.error(ErrorKind::InvalidOp, Some(0))),
+                (_, InterimOp::Mem(..), _) => Err(line.error(ErrorKind::InvalidOp, Some(0))),
+                (_, _, InterimOp::Mem(..)) => Ok(()),
+                _ => Err(line.error(ErrorKind::InvalidOp, Some(1))),
+            }
+        },
+        Instruction::NOT => {
+            let (Some(_), Some(op2)) = (&line.op1, &line.op2) else {
+                return Err(line.error(ErrorKind::TooFewOps, None));
+            };
+            if line.op3.is_some() {
+                return Err(line.error(ErrorKind::TooManyOps, Some(2)));
+            }
+            match op2 {
+                InterimOp::Lable(..) |
+                InterimOp::Direct(..) => Err(line.error(ErrorKind::InvalidOp, Some(1))),
+                _ => Ok(()),
+            }
+        },
+        Instruction::ADD |
+        Instruction::SUB |
+        Instruction::MUL |
+        Instruction::DIV |
+        Instruction::MOD |
+        Instruction::AND |
+        Instruction::OR  |
+        Instruction::XOR |
+        Instruction::SHL |
+        Instruction::SHR => {
+            if line.op1.is_none() || line.op2.is_none()  {
+                return Err(line.error(ErrorKind::TooFewOps, None));
+            }
+            match &line.op3 {
+                Some(InterimOp::Lable(..)) |
+                Some(InterimOp::Direct(..)) => Err(line.error(ErrorKind::InvalidOp, Some(2))),
+                Some(_) => Ok(()),
+                None => Err(line.error(ErrorKind::TooFewOps, None)),
+            }
+        }
+    }
+}
+
+fn is_directive(tokens : &[Token]) -> bool {
+    tokens.first().is_some_and(|t| t.text.starts_with('.'))
+}
+
+/// `false` if a name would be confused with a number, register or instruction
+pub(super) fn valid_name(name : &str) -> bool {
+    let first = match name.chars().next() {
+        Some(c) => c,
+        None => return false,
+    };
+    let upper = name.to_uppercase();
+    !(first.is_ascii_digit() || "#[.-'".contains(first) || name.contains(':')
+      || ["PC", "R1", "R2", "RT"].contains(&upper.as_str())
+      || (upper.starts_with("IO") && upper[2..].parse::<u16>().is_ok())
+      || get_instruction(name).is_ok())
+}
+
+/// find the names defined by `.equ NAME value` and `.alias NAME register` directives,
+/// `.def` is the same as `.alias`
+fn get_symbols(source_lines : &[SourceLine]) -> (HashMap<String, Symbol>, Vec<CodeError>) {
+    let mut symbols = HashMap::new();
+    let mut errors = Vec::new();
+
+    for line in source_lines {
+        if !is_directive(&line.tokens) {
+            continue;
         }
+        match get_symbol(&line.tokens, &symbols) {
+            Ok((name, symbol)) => { symbols.insert(name, symbol); },
+            Err(e) => errors.push(e.expanded_from(line.expanded_from)),
+        }
+    }
+    (symbols, errors)
+}
+
+fn get_symbol(tokens : &[Token], symbols : &HashMap<String, Symbol>) -> Result<(String, Symbol), CodeError> {
+    let directive = tokens[0].text.to_lowercase();
+    if !matches!(directive.as_str(), ".equ" | ".alias" | ".def") {
+        return Err(CodeError::new(ErrorKind::UnknownDirective, tokens[0].span));
     }
+    if tokens.len() < 3 {
+        return Err(CodeError::new(ErrorKind::TooFewOps, tokens[0].span));
+    }
+    if tokens.len() > 3 {
+        return Err(CodeError::new(ErrorKind::TooManyOps, tokens[3].span));
+    }
+    let (name, value) = (&tokens[1], &tokens[2]);
+    if !valid_name(&name.text) {
+        return Err(CodeError::new(ErrorKind::InvalidName, name.span));
+    }
+    if symbols.contains_key(&name.text) {
+        return Err(CodeError::new(ErrorKind::DuplicateName(name.text.clone()), name.span));
+    }
+    let symbol = if directive == ".equ" {
+        let number = value.text.strip_prefix('#').unwrap_or(&value.text);
+        Symbol::Const(parse_number(number).map_err(|kind| CodeError::new(kind, value.span))?)
+    } else {
+        match get_operand(&value.text, value.span, &HashMap::new())? {
+            InterimOp::Reg(reg) => Symbol::Alias(reg),
+            _ => return Err(CodeError::new(ErrorKind::InvalidOp, value.span)),
+        }
+    };
+    Ok((name.text.clone(), symbol))
 }
 
-fn get_lines(program_code : &str) -> Result<Vec<InterimLine>, CodeError> {
+/// parse every line, collecting all errors instead of stopping at the first one,
+/// lines with errors are still returned so their lables can be resolved
+fn parse_lines(program_code : &str) -> (Vec<InterimLine>, Vec<CodeError>) {
     let mut lines = Vec::new();
+    let (source_lines, mut errors) = expand_macros(program_code);
+    let (symbols, symbol_errors) = get_symbols(&source_lines);
+    errors.extend(symbol_errors);
     let mut line = InterimLine::new();
 
-    for (line_index, l) in program_code.split('\n').enumerate() {
-        for w in l.split(" ") {
-            if w.len() == 0 {
-                 continue;
-            }
-            let w : Vec<&str> = w.split(";").collect();
-            if w.len() != 1 {
-                break;
-            }
-            let w = w.as_slice()[0];
-            if w.len() == 0 {
-                continue;
-            }
+    for source in source_lines {
+        let mut line_ok = true;
+        if is_directive(&source.tokens) {
+            continue;
+        }
+        let first_error = errors.len();
+        for token in source.tokens {
+            let w = token.text.as_str();
 
             //add to line
             match line.instr {
@@ -231,73 +724,115 @@ fn get_lines(program_code : &str) -> Result<Vec<InterimLine>, CodeError> {
                 None => line.instr = match get_instruction(w) {
                     Err(_) => {
                         if !w.ends_with(":") {
-                            return Err(CodeError::UnknownInst(line_index));
+                            errors.push(CodeError::new(ErrorKind::UnknownInst, token.span));
+                            line_ok = false;
+                            line.instr_span = token.span;
+                            line.expanded_from = source.expanded_from;
+                            Some(Instruction::NOP)
                         } else {
                             if line.lable.is_some() {
                                 line.instr = Some(Instruction::NOP);
                                 lines.push(line);
-                                line = InterimLine::new();   
+                                line = InterimLine::new();
                             }
                             line.lable = Some(w[0..w.len()-1].to_string());
+                            line.lable_span = Span::new(token.span.line, token.span.start, token.span.end - 1);
+                            line.expanded_from = source.expanded_from;
+                            if symbols.contains_key(&w[0..w.len()-1]) {
+                                errors.push(CodeError::new(ErrorKind::DuplicateName(w[0..w.len()-1].to_string()), line.lable_span));
+                            }
                             None
                         }
                     }
-                    Ok(instr) => Some(instr),
+                    Ok(instr) => {
+                        line.instr_span = token.span;
+                        line.expanded_from = source.expanded_from;
+                        Some(instr)
+                    },
                 },
+                //skip operands of an unknown instruction
+                _ if !line_ok => (),
                 //add Operand
                 _ => {
-                    if line.op1.is_none() {
-                        line.op1 = Some(get_operand(w, line_index)?);
+                    let op_index = if line.op1.is_none() {
+                        0
                     } else if line.op2.is_none() {
-                        line.op2 = Some(get_operand(w, line_index)?);
+                        1
                     } else if line.op3.is_none() {
-                        line.op3 = Some(get_operand(w, line_index)?);
+                        2
                     } else {
-                        return Err(CodeError::TooManyOps(line_index));
+                        errors.push(CodeError::new(ErrorKind::TooManyOps, token.span));
+                        line_ok = false;
+                        continue;
+                    };
+                    let op = match get_operand(w, token.span, &symbols) {
+                        Ok(op) => op,
+                        Err(e) => {
+                            errors.push(e);
+                            line_ok = false;
+                            InterimOp::Direct(0)
+                        }
+                    };
+                    line.op_spans[op_index] = token.span;
+                    match op_index {
+                        0 => line.op1 = Some(op),
+                        1 => line.op2 = Some(op),
+                        _ => line.op3 = Some(op),
                     }
                 }
             }
 
         }
-        match line.instr {
-            Some(_) => {
-                check_line(&line, line_index)?;
-                lines.push(line);
-                line = InterimLine::new();
-            },
-            None => (),
+        if line.instr.is_some() {
+            if line_ok {
+                if let Err(e) = check_line(&line) {
+                    errors.push(e);
+                }
+            }
+            lines.push(line);
+            line = InterimLine::new();
+        }
+        for e in errors[first_error..].iter_mut() {
+            e.expanded_from = source.expanded_from;
         }
     }
 
-    Ok(lines)
+    (lines, errors)
 }
 
-fn to_final_op(op : &Option<InterimOp>, lable_hash : &HashMap<String, u16>) -> Result<Option<Operand>, CodeError> {
+fn to_final_op(op : &Option<InterimOp>, span : Span, lable_hash : &HashMap<String, u16>) -> Result<Option<Operand>, CodeError> {
     Ok(match op {
         Some(int_op) => match int_op {
             InterimOp::Reg(reg) => Some(Operand::Reg(*reg)),
-            InterimOp::Direct(num) => Some(Operand::Direct(*num as i16)),
+            InterimOp::Direct(num) => Some(Operand::Direct(*num)),
+            InterimOp::Mem(address) => Some(Operand::Mem(*address)),
             InterimOp::Lable(lable) => {
                 if lable_hash.contains_key(lable) {
                     Some(Operand::Direct(lable_hash[lable] as i16))
                 } else {
-                    return Err(CodeError::MissingLable(0));
+                    return Err(CodeError::new(ErrorKind::UnknownLable(lable.clone()), span));
                 }
             }
-   
+
             },
         None => None,
     })
 }
 
 
-fn to_final_lines(lines: Vec<InterimLine>) -> Result<Vec<Line>, CodeError> {
+fn to_final_lines(lines: Vec<InterimLine>) -> Result<Vec<Line>, Vec<CodeError>> {
     let mut lable_hash : HashMap<String, u16> = HashMap::new();
 
+    let mut errors = Vec::new();
+
     //build label hash map
     for (i, l) in lines.iter().enumerate() {
-        if l.lable.is_some() {
-            lable_hash.insert(l.lable.as_ref().unwrap().to_string(), i as u16);
+        if let Some(lable) = &l.lable {
+            if lable_hash.contains_key(lable) {
+                errors.push(CodeError::new(ErrorKind::DuplicateLable(lable.clone()), l.lable_span).expanded_from(l.expanded_from));
+            } else {
+                lable_hash.insert(lable.to_string(), i as u16);
+            }
         }
     }
 
@@ -305,32 +840,143 @@ fn to_final_lines(lines: Vec<InterimLine>) -> Result<Vec<Line>, CodeError> {
     let mut final_lines = Vec::new();
 
     for l in lines {
+        let mut final_op = |op : &Option<InterimOp>, span : Span| {
+            to_final_op(op, span, &lable_hash).unwrap_or_else(|e| {
+                errors.push(e.expanded_from(l.expanded_from));
+                None
+            })
+        };
         let new_line = Line {
             instr : l.instr.unwrap(),
-            op1   : to_final_op(&l.op1, &lable_hash)?,
-            op2   : to_final_op(&l.op2, &lable_hash)?,
-            op3   : to_final_op(&l.op3, &lable_hash)?,
+            op1   : final_op(&l.op1, l.op_spans[0]),
+            op2   : final_op(&l.op2, l.op_spans[1]),
+            op3   : final_op(&l.op3, l.op_spans[2]),
         };
         final_lines.push(new_line);
     }
-    
-    Ok(final_lines)
+
+    if errors.is_empty() {
+        Ok(final_lines)
+    } else {
+        Err(errors)
+    }
+}
+
+/// warn about lables that are never used and code following
+/// an unconditional jump or halt that has no lable to reach it
+fn get_warnings(lines : &[InterimLine]) -> Vec<CodeWarning> {
+    let mut warnings = Vec::new();
+    let used_lables : Vec<&String> = lines.iter()
+        .flat_map(|l| [&l.op1, &l.op2, &l.op3])
+        .filter_map(|op| match op {
+            Some(InterimOp::Lable(lable)) => Some(lable),
+            _ => None,
+        })
+        .collect();
+
+    let mut reachable = true;
+    for l in lines {
+        if let Some(lable) = &l.lable {
+            if !used_lables.contains(&lable) {
+                warnings.push(CodeWarning {
+                    kind : WarningKind::UnusedLable(lable.clone()),
+                    span : l.lable_span,
+                    expanded_from : l.expanded_from,
+                });
+            }
+            reachable = true;
+        } else if !reachable {
+            warnings.push(CodeWarning {
+                kind : WarningKind::Unreachable,
+                span : l.instr_span,
+                expanded_from : l.expanded_from,
+            });
+            //only warn once for each unreachable block
+            reachable = true;
+            continue;
+        }
+        if let Some(Instruction::BRC | Instruction::HLT | Instruction::RET) = l.instr {
+            reachable = false;
+        }
+    }
+    warnings
+}
+
+/// errors for code that doesn't fit in the limits of a `McModel`
+fn check_limits(text_input : &str, lines : &[InterimLine], model : &McModel) -> Vec<CodeError> {
+    let mut errors = Vec::new();
+    let source_lines : Vec<&str> = text_input.trim_end().split('\n').collect();
+    if source_lines.len() > model.max_source_lines {
+        let line = model.max_source_lines;
+        errors.push(CodeError::new(ErrorKind::TooManyLines(line), Span::new(line, 0, source_lines[line].len())));
+    }
+    if lines.len() > model.max_instructions {
+        let line = &lines[model.max_instructions];
+        errors.push(CodeError::new(ErrorKind::TooManyInstructions(model.max_instructions), line.span()).expanded_from(line.expanded_from));
+    }
+    for l in lines {
+        for (i, op) in [&l.op1, &l.op2, &l.op3].iter().enumerate() {
+            match op {
+                Some(InterimOp::Reg(reg)) | Some(InterimOp::Mem(Address::Reg(reg))) if !model.has_register(*reg) => {
+                    errors.push(l.error(ErrorKind::MissingRegister(*reg), Some(i)));
+                },
+                _ => (),
+            }
+        }
+    }
+    errors
 }
 
 /// Converts code to a list of `Instruction`s
 /// guarentees each `Instruction` has an appropriate number of `Operand`s
-/// and replaces code lables with direct values
-pub fn get_program_instructions(text_input : &str) -> Result<Vec<Line>, CodeError> {
-    let interim_lines = get_lines(text_input)?;
-    to_final_lines(interim_lines)
+/// and replaces code lables with direct values,
+/// returns every error found in the code if it can't be assembled
+pub fn assemble(text_input : &str) -> Result<Assembly, Vec<CodeError>> {
+    assemble_with(text_input, None)
 }
 
+/// same as `assemble`, also errors if the code doesn't fit in the limits of the `McModel`
+pub fn assemble_for(text_input : &str, model : &McModel) -> Result<Assembly, Vec<CodeError>> {
+    assemble_with(text_input, Some(model))
+}
+
+fn assemble_with(text_input : &str, model : Option<&McModel>) -> Result<Assembly, Vec<CodeError>> {
+    let (interim_lines, mut errors) = parse_lines(text_input);
+    if let Some(model) = model {
+        errors.extend(check_limits(text_input, &interim_lines, model));
+    }
+    let mut warnings = get_warnings(&interim_lines);
+    let source_lines = interim_lines.iter().map(|l| l.source_line()).collect();
+    match to_final_lines(interim_lines) {
+        Ok(lines) if errors.is_empty() => {
+            warnings.sort_by_key(|w| (w.span.line, w.span.start));
+            return Ok(Assembly { lines, warnings, source_lines });
+        },
+        Ok(_) => (),
+        Err(lable_errors) => errors.extend(lable_errors),
+    }
+    errors.sort_by_key(|e| (e.span.line, e.span.start));
+    Err(errors)
+}
 
+/// same as `assemble` without the warnings
+pub fn get_program_instructions(text_input : &str) -> Result<Vec<Line>, Vec<CodeError>> {
+    Ok(assemble(text_input)?.lines)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn get_lines(program_code : &str) -> Result<Vec<InterimLine>, Vec<CodeError>> {
+        let (lines, errors) = parse_lines(program_code);
+        if errors.is_empty() {
+            Ok(lines)
+        } else {
+            Err(errors)
+        }
+    }
+
     #[test]
     fn check_line_cmp() {
         let line = InterimLine {
@@ -338,9 +984,10 @@ mod tests {
             instr : Some(Instruction::CMP),
             op1 : Some(InterimOp::Reg(Register::R1)),
             op2 : Some(InterimOp::Direct(65)),
-            op3 : None
+            op3 : None,
+            ..InterimLine::new()
         };
-        assert!(check_line(&line, 0).is_ok());
+        assert!(check_line(&line).is_ok());
     }
 
      #[test]
@@ -351,8 +998,9 @@ mod tests {
             op1 : Some(InterimOp::Reg(Register::R1)),
             op2 : Some(InterimOp::Reg(Register::R2)),
             op3 : Some(InterimOp::Direct(1000)),
+            ..InterimLine::new()
         };
-        assert!(check_line(&line, 0).is_err());
+        assert!(check_line(&line).is_err());
     }
 
     #[test]
@@ -426,7 +1074,7 @@ HLT
                 if lines.is_err() {
                     let lines = lines.unwrap_err();
                     println!("error {:?}", lines);
-                    assert!(matches!(lines, CodeError::OutOfRangeIO(3)))
+                    assert!(lines[0].kind == ErrorKind::OutOfRangeIO && lines[0].span.line == 3)
                 } else {
                     panic!("should fail");
                 }
@@ -471,4 +1119,328 @@ hlt
             
     }
 
+
+    #[test]
+    fn parse_bitwise_instructions() {
+        let code =
+"
+MOD R1 #2 R2
+AND R1 #15 R2
+or r1 r2 r1
+xor r1 r2 io0
+not r1 r2
+shl r1 #1 r1
+shr r1 #1 r1
+";
+        let lines = get_lines(code).unwrap();
+        assert!(lines.len() == 7);
+        assert!(matches!(lines[0].instr.as_ref().unwrap(), Instruction::MOD));
+        assert!(matches!(lines[4].instr.as_ref().unwrap(), Instruction::NOT));
+        assert!(matches!(lines[4].op2.as_ref().unwrap(), InterimOp::Reg(Register::R2)));
+        assert!(matches!(lines[6].instr.as_ref().unwrap(), Instruction::SHR));
+    }
+
+    #[test]
+    fn check_line_not_err() {
+        let line = InterimLine {
+            lable : None,
+            instr : Some(Instruction::NOT),
+            op1 : Some(InterimOp::Reg(Register::R1)),
+            op2 : Some(InterimOp::Direct(3)),
+            op3 : None,
+            ..InterimLine::new()
+        };
+        assert!(matches!(check_line(&line), Err(CodeError { kind : ErrorKind::InvalidOp, .. })));
+        let line = InterimLine {
+            op2 : Some(InterimOp::Reg(Register::R2)),
+            op3 : Some(InterimOp::Reg(Register::R1)),
+            ..line
+        };
+        assert!(matches!(check_line(&line), Err(CodeError { kind : ErrorKind::TooManyOps, .. })));
+    }
+
+
+    #[test]
+    fn parse_memory_operands() {
+        let code =
+"
+LD [#3] R1
+LD [r2] io1
+ST io0 [R1]
+ST #5 [#15]
+HLT
+";
+        let lines = get_lines(code).unwrap();
+        assert!(matches!(lines[0].op1.as_ref().unwrap(), InterimOp::Mem(Address::Direct(3))));
+        assert!(matches!(lines[1].op1.as_ref().unwrap(), InterimOp::Mem(Address::Reg(Register::R2))));
+        assert!(matches!(lines[2].op2.as_ref().unwrap(), InterimOp::Mem(Address::Reg(Register::R1))));
+
+        assert!(get_lines("LD #3 R1").is_err());
+        assert!(get_lines("LD [#3] #4").is_err());
+        assert!(get_lines("ST [#3] [#4]").is_err());
+        assert!(get_lines("ADD [#3] #1 R1").is_err());
+        assert!(get_lines("LD [io0] R1").is_err());
+    }
+
+    #[test]
+    fn collects_all_errors_with_spans() {
+        let code =
+"
+ADD #10 #0 R1
+FOO R1
+ADD R1 #x R1
+BRC nowhere
+CMP R1 R2 R1
+";
+        let errors = get_program_instructions(code).unwrap_err();
+        assert!(errors.len() == 4);
+        assert!(errors[0] == CodeError::new(ErrorKind::UnknownInst, Span::new(2, 0, 3)));
+        assert!(errors[1] == CodeError::new(ErrorKind::UnknownNumber, Span::new(3, 7, 9)));
+        assert!(errors[2] == CodeError::new(ErrorKind::UnknownLable(String::from("nowhere")), Span::new(4, 4, 11)));
+        assert!(errors[3] == CodeError::new(ErrorKind::TooManyOps, Span::new(5, 10, 12)));
+        assert!(errors[2].to_string() == "line 5, col 5-11: lable \"nowhere\" is not defined");
+    }
+
+    #[test]
+    fn tokenise_spans() {
+        let tokens = tokenise("loop:\tADD  R1 #1 R1 ; comment R2", 7);
+        let texts : Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert!(texts == vec!["loop:", "ADD", "R1", "#1", "R1"]);
+        assert!(tokens[1].span == Span::new(7, 6, 9));
+        assert!(tokens[4].span == Span::new(7, 17, 19));
+    }
+
+    #[test]
+    fn duplicate_lable() {
+        let code =
+"
+loop:
+ADD R1 #1 R1
+loop:
+BRC loop
+";
+        let errors = get_program_instructions(code).unwrap_err();
+        assert!(errors.len() == 1);
+        assert!(errors[0] == CodeError::new(ErrorKind::DuplicateLable(String::from("loop")), Span::new(3, 0, 4)));
+    }
+
+    #[test]
+    fn unused_lable_and_unreachable_warnings() {
+        let code =
+"
+start:
+ADD R1 #1 R1
+CMP R1 #5
+BLT skip
+BRC end
+ADD R1 #2 R1
+SUB R1 #1 R1
+skip:
+HLT
+end:
+NOP
+";
+        let warnings = assemble(code).unwrap().warnings;
+        assert!(warnings.len() == 2);
+        assert!(warnings[0] == CodeWarning::new(WarningKind::UnusedLable(String::from("start")), Span::new(1, 0, 5)));
+        assert!(warnings[1] == CodeWarning::new(WarningKind::Unreachable, Span::new(6, 0, 3)));
+
+        let warnings = assemble("HLT\nNOP\nNOP").unwrap().warnings;
+        assert!(warnings.len() == 1);
+    }
+
+    #[test]
+    fn number_literals() {
+        assert!(parse_number("42") == Ok(42));
+        assert!(parse_number("-5") == Ok(-5));
+        assert!(parse_number("0x1F") == Ok(31));
+        assert!(parse_number("-0x10") == Ok(-16));
+        assert!(parse_number("0b1010") == Ok(10));
+        assert!(parse_number("'A'") == Ok(65));
+        assert!(parse_number("' '") == Ok(32));
+        assert!(parse_number("'\\n'") == Ok(10));
+        assert!(parse_number("-32768") == Ok(i16::MIN));
+        assert!(parse_number("32768") == Err(ErrorKind::NumberOutOfRange));
+        assert!(parse_number("40000") == Err(ErrorKind::NumberOutOfRange));
+        assert!(parse_number("0xFFFF") == Err(ErrorKind::NumberOutOfRange));
+        assert!(parse_number("99999999999999999999") == Err(ErrorKind::NumberOutOfRange));
+        assert!(parse_number("0b102") == Err(ErrorKind::UnknownNumber));
+        assert!(parse_number("0x") == Err(ErrorKind::UnknownNumber));
+        assert!(parse_number("'AB'") == Err(ErrorKind::UnknownNumber));
+        assert!(parse_number("+4") == Err(ErrorKind::UnknownNumber));
+    }
+
+    #[test]
+    fn character_literals_in_code() {
+        let lines = get_program_instructions("ADD #' ' #';' R1 ; comment\nCMP R1 #'\\''").unwrap();
+        assert!(lines.len() == 2);
+        assert!(matches!(lines[0].op1, Some(Operand::Direct(32))));
+        assert!(matches!(lines[0].op2, Some(Operand::Direct(59))));
+        assert!(matches!(lines[1].op2, Some(Operand::Direct(39))));
+
+        let errors = get_program_instructions("ADD #40000 #0 R1").unwrap_err();
+        assert!(errors[0] == CodeError::new(ErrorKind::NumberOutOfRange, Span::new(0, 4, 10)));
+    }
+
+    #[test]
+    fn constants_and_aliases() {
+        let code =
+"
+.equ LIMIT 0x10
+.equ START #-1
+.alias sensor io1
+.def count r1
+    ADD START #0 count
+loop:
+    ADD count #1 count
+    ST count [count]
+    CMP count LIMIT
+    BLT loop
+    LD [LIMIT] sensor
+    HLT
+";
+        let lines = get_program_instructions(code).unwrap();
+        assert!(lines.len() == 7);
+        assert!(matches!(lines[0].op1, Some(Operand::Direct(-1))));
+        assert!(matches!(lines[0].op3, Some(Operand::Reg(Register::R1))));
+        assert!(matches!(lines[2].op2, Some(Operand::Mem(Address::Reg(Register::R1)))));
+        assert!(matches!(lines[3].op2, Some(Operand::Direct(16))));
+        assert!(matches!(lines[5].op1, Some(Operand::Mem(Address::Direct(16)))));
+        assert!(matches!(lines[5].op2, Some(Operand::Reg(Register::RIO(1)))));
+    }
+
+    #[test]
+    fn constant_errors() {
+        let code =
+"
+.equ LIMIT 10
+.equ LIMIT 11
+.equ R1 4
+.alias out #4
+.equ big 70000
+.org 5
+LIMIT:
+HLT
+";
+        let errors = get_program_instructions(code).unwrap_err();
+        let kinds : Vec<&ErrorKind> = errors.iter().map(|e| &e.kind).collect();
+        assert!(kinds == vec![
+            &ErrorKind::DuplicateName(String::from("LIMIT")),
+            &ErrorKind::InvalidName,
+            &ErrorKind::InvalidOp,
+            &ErrorKind::NumberOutOfRange,
+            &ErrorKind::UnknownDirective,
+            &ErrorKind::DuplicateName(String::from("LIMIT")),
+        ]);
+        assert!(errors[0].span == Span::new(2, 5, 10));
+    }
+
+    #[test]
+    fn macro_expansion() {
+        let code =
+"
+.macro countdown reg
+loop: SUB reg #1 reg
+      CMP reg #0
+      BGT loop
+.endm
+.macro clear addr
+      ST #0 [addr]
+.endm
+countdown R1
+countdown R2
+clear #3
+HLT
+";
+        let lines = get_program_instructions(code).unwrap();
+        assert!(lines.len() == 8);
+        //each expansion branches back to its own lable
+        assert!(matches!(lines[2].op1, Some(Operand::Direct(0))));
+        assert!(matches!(lines[5].op1, Some(Operand::Direct(3))));
+        assert!(matches!(lines[3].op1, Some(Operand::Reg(Register::R2))));
+        assert!(matches!(lines[6].op2, Some(Operand::Mem(Address::Direct(3)))));
+    }
+
+    #[test]
+    fn macro_errors() {
+        let code =
+"
+.macro bad a
+  ADD a #1 IO9
+.endm
+bad R1
+bad R1 R2
+.endm
+.macro open
+";
+        let errors = get_program_instructions(code).unwrap_err();
+        let kinds : Vec<&ErrorKind> = errors.iter().map(|e| &e.kind).collect();
+        assert!(kinds == vec![
+            &ErrorKind::OutOfRangeIO,
+            &ErrorKind::WrongArgCount(1),
+            &ErrorKind::UnmatchedEndm,
+            &ErrorKind::UnterminatedMacro,
+        ]);
+        //the error is in the macro body and was used on line 4
+        assert!(errors[0].span == Span::new(2, 11, 14));
+        assert!(errors[0].expanded_from == Some(Span::new(4, 0, 3)));
+        assert!(errors[0].to_string().ends_with("(in macro used on line 5)"));
+        assert!(errors[1].expanded_from.is_none());
+
+        //a macro using itself is one error where it first repeats
+        let errors = get_program_instructions(".macro m\nm\nm\nm\nm\n.endm\nm").unwrap_err();
+        assert!(errors == vec![
+            CodeError::new(ErrorKind::RecursiveMacro(String::from("m")), Span::new(1, 0, 1)).expanded_from(Some(Span::new(6, 0, 1))),
+        ], "{:?}", errors);
+
+        //macros that each use the last one 4 times expand to 4^8 lines
+        let mut code = String::from(".macro m0\nADD R1 #1 R1\n.endm\n");
+        for i in 1..=8 {
+            code += &format!(".macro m{}\n{}.endm\n", i, format!("m{}\n", i - 1).repeat(4));
+        }
+        code += "m8\nm8";
+        let errors = get_program_instructions(&code).unwrap_err();
+        let line = code.matches('\n').count() - 1;
+        assert!(errors == vec![
+            CodeError::new(ErrorKind::MacroTooLong(4096), Span::new(line, 0, 2)),
+        ], "{:?}", errors);
+
+        //`@` is only used for the lables of macro expansions
+        let errors = get_program_instructions(".macro m\nx: BRC x\n.endm\nm\nx@1: BRC x@1\nADD #'@' #0 R1").unwrap_err();
+        assert!(errors == vec![
+            CodeError::new(ErrorKind::MisformedLable, Span::new(4, 0, 4)),
+            CodeError::new(ErrorKind::MisformedLable, Span::new(4, 9, 12)),
+        ], "{:?}", errors);
+    }
+
+    #[test]
+    fn source_line_of_each_instruction() {
+        let code = "; add one\n.equ ONE 1\nstart:\n    ADD R1 ONE R1\n\nend: other:\n    BRC start\n";
+        let assembly = assemble(code).unwrap();
+        assert!(assembly.lines.len() == 3);
+        assert!(assembly.source_lines == vec![3, 5, 6]);
+    }
+
+    #[test]
+    fn model_limits() {
+        let small = McModel::from_name("zl001s").unwrap();
+        let code = "ADD R1 #1 R2\nLD [R2] R1\nHLT";
+        let errors = assemble_for(code, small).unwrap_err();
+        assert!(errors.len() == 2);
+        assert!(errors[0] == CodeError::new(ErrorKind::MissingRegister(Register::R2), Span::new(0, 10, 12)));
+        assert!(errors[1] == CodeError::new(ErrorKind::MissingRegister(Register::R2), Span::new(1, 3, 7)));
+        assert!(assemble_for(code, McModel::default_model()).is_ok());
+
+        let long = "NOP\n".repeat(small.max_instructions + 1);
+        let errors = assemble_for(&long, small).unwrap_err();
+        assert!(errors == vec![CodeError::new(ErrorKind::TooManyInstructions(small.max_instructions), Span::new(12, 0, 3))]);
+        //lables on their own are a NOP each, the error is on the lable
+        let lables : String = (0..=small.max_instructions + 1).map(|i| format!("l{}:\n", i)).collect::<String>() + "HLT";
+        let errors = assemble_for(&lables, small).unwrap_err();
+        assert!(errors == vec![CodeError::new(ErrorKind::TooManyInstructions(small.max_instructions), Span::new(12, 0, 3))], "{:?}", errors);
+
+        let commented = ";\n".repeat(small.max_source_lines) + "HLT\n\n";
+        let errors = assemble_for(&commented, small).unwrap_err();
+        assert!(errors == vec![CodeError::new(ErrorKind::TooManyLines(small.max_source_lines), Span::new(24, 0, 3))]);
+    }
 }
@@ -0,0 +1,127 @@
+//! Conditions that pause a running circuit, set on a `Microcontroller`
+//!
+//! written as `line 4`, `R1 == 5` or `io2 written`, conditions can also be set from
+//! the code with a comment such as `; break R1 == 5`
+
+use super::assembler::{parse_number, tokenise_with_comment, Register, IO_REGISTER_COUNT};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A condition checked on each step of a `Microcontroller`. a line is hit every time
+/// one of its instructions starts, the others are hit when they become true
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// an instruction from the source line at this index starts, a chip waiting on it
+    /// to read in doesn't hit it again
+    Line(usize),
+    /// a register changed to the value
+    RegisterEquals(Register, i16),
+    /// a value was written to the io register
+    IoWritten(usize),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line + 1),
+            Breakpoint::RegisterEquals(reg, value) => write!(f, "{} == {}", reg, value),
+            Breakpoint::IoWritten(io) => write!(f, "io{} written", io),
+        }
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(text : &str) -> Result<Self, Self::Err> {
+        let words : Vec<&str> = text.split_whitespace().collect();
+        //only keywords and registers ignore case, the value is parsed as written so `'a'` stays lowercase
+        let upper = |w : &str| w.to_uppercase();
+        match words.as_slice() {
+            [line, n] if upper(line) == "LINE" => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Breakpoint::Line(n - 1)),
+                _ => Err(format!("not a line number: {}", n)),
+            },
+            [io, written] if upper(written) == "WRITTEN" => match upper(io).strip_prefix("IO").map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n < IO_REGISTER_COUNT => Ok(Breakpoint::IoWritten(n)),
+                _ => Err(format!("not an io register: {}", io)),
+            },
+            [reg, "==", value] => {
+                let reg = match upper(reg).as_str() {
+                    "PC" => Register::PC,
+                    "R1" => Register::R1,
+                    "R2" => Register::R2,
+                    "RT" => Register::RT,
+                    _ => return Err(format!("not a register: {}", reg)),
+                };
+                let value = value.strip_prefix('#').unwrap_or(value);
+                let value = parse_number(value).map_err(|e| format!("{}: {}", e, value))?;
+                Ok(Breakpoint::RegisterEquals(reg, value))
+            },
+            _ => Err(format!("unknown breakpoint {}, expected `line N`, `REG == N` or `ioN written`", text.trim())),
+        }
+    }
+}
+
+/// the conditions set by `; break CONDITION` comments in the code, line breakpoints are
+/// left to the editor. returns a message for each comment that isn't a condition
+pub fn comment_breakpoints(code : &str) -> Result<Vec<Breakpoint>, Vec<String>> {
+    let mut breakpoints = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in code.lines().enumerate() {
+        let comment = match tokenise_with_comment(line, i).1 {
+            Some(start) => line[start + 1..].trim(),
+            None => continue,
+        };
+        let condition = match comment.split_once(char::is_whitespace) {
+            Some((word, condition)) if word.eq_ignore_ascii_case("break") => condition,
+            _ => continue,
+        };
+        match condition.parse() {
+            Ok(Breakpoint::Line(_)) => errors.push(format!("line {}: line breakpoints are set with ctrl + b", i + 1)),
+            Ok(bp) if !breakpoints.contains(&bp) => breakpoints.push(bp),
+            Ok(_) => (),
+            Err(e) => errors.push(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(breakpoints)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_breakpoints() {
+        assert!("line 4".parse() == Ok(Breakpoint::Line(3)));
+        assert!("r1 == 5".parse() == Ok(Breakpoint::RegisterEquals(Register::R1, 5)));
+        assert!("RT == #0x4".parse() == Ok(Breakpoint::RegisterEquals(Register::RT, 4)));
+        assert!("IO2 written".parse() == Ok(Breakpoint::IoWritten(2)));
+        assert!("R1 == 'A'".parse() == Ok(Breakpoint::RegisterEquals(Register::R1, 65)));
+        assert!("R2 == #'a'".parse() == Ok(Breakpoint::RegisterEquals(Register::R2, 97)));
+        assert!("pc == 0X1F".parse() == Ok(Breakpoint::RegisterEquals(Register::PC, 31)));
+        for text in ["line 0", "io4 written", "R3 == 1", "R1 = 1", "halt"] {
+            assert!(text.parse::<Breakpoint>().is_err(), "{}", text);
+        }
+        for bp in [Breakpoint::Line(0), Breakpoint::RegisterEquals(Register::R2, -3), Breakpoint::IoWritten(1)] {
+            assert!(bp.to_string().parse() == Ok(bp));
+        }
+    }
+
+    #[test]
+    fn breakpoints_from_comments() {
+        let code = "ADD #1 #0 R1 ; break R1 == 1\n;BREAK io2 written\nADD #';' #0 R2 ; breaks here\n; break R1 == 1\nHLT";
+        assert!(comment_breakpoints(code) == Ok(vec![
+            Breakpoint::RegisterEquals(Register::R1, 1),
+            Breakpoint::IoWritten(2),
+        ]));
+        let errors = comment_breakpoints("; break line 2\nHLT ; break R4 == 1").unwrap_err();
+        assert!(errors.len() == 2);
+        assert!(errors[1].starts_with("line 2:"));
+    }
+}
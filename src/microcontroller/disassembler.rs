@@ -0,0 +1,113 @@
+//! Turns assembled `Line`s back into code that assembles to the same `Line`s
+
+use super::assembler::{Instruction, Operand, Line};
+
+use std::collections::BTreeSet;
+
+/// the line a branch or subroutine call goes to, if it has a direct target
+fn branch_target(line : &Line) -> Option<i16> {
+    match (line.instr, line.op1) {
+        (Instruction::BRC | Instruction::BEQ | Instruction::BGT | Instruction::BLT | Instruction::JSR,
+         Some(Operand::Direct(target))) => Some(target),
+        _ => None,
+    }
+}
+
+fn lable_name(target : usize) -> String {
+    format!("l{}", target)
+}
+
+/// Converts `Line`s to code with one instruction per line, so line `n` of the code is at pc `n`.
+/// Branch targets get lables named `l` followed by the line number, a branch to one past
+/// the last line gets an extra `HLT` to hold its lable, which is what the `Program` does anyway
+pub fn disassemble(lines : &[Line]) -> String {
+    let targets : BTreeSet<usize> = lines.iter()
+        .filter_map(branch_target)
+        .filter(|t| *t >= 0 && *t as usize <= lines.len())
+        .map(|t| t as usize)
+        .collect();
+    let lable_width = targets.iter()
+        .map(|t| lable_name(*t).len() + 2)
+        .max()
+        .unwrap_or(0);
+
+    let mut code = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let lable = if targets.contains(&i) {
+            format!("{}:", lable_name(i))
+        } else {
+            String::new()
+        };
+        let mut text = format!("{:width$}{:?}", lable, line.instr, width = lable_width);
+        for (op_index, op) in [line.op1, line.op2, line.op3].iter().enumerate() {
+            let op = match op {
+                Some(op) => op,
+                None => break,
+            };
+            match branch_target(line) {
+                Some(t) if op_index == 0 && targets.contains(&(t as usize)) => {
+                    text.push(' ');
+                    text.push_str(&lable_name(t as usize));
+                },
+                _ => text.push_str(&format!(" {}", op)),
+            }
+        }
+        code.push(text);
+    }
+    if targets.contains(&lines.len()) {
+        code.push(format!("{:width$}HLT", format!("{}:", lable_name(lines.len())), width = lable_width));
+    }
+    code.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assembler::get_program_instructions;
+
+    fn round_trip(code : &str) {
+        let lines = get_program_instructions(code).unwrap();
+        let text = disassemble(&lines);
+        let again = get_program_instructions(&text).unwrap();
+        assert!(format!("{:?}", lines) == format!("{:?}", again), "{}", text);
+        //disassembling again changes nothing
+        assert!(disassemble(&again) == text);
+    }
+
+    #[test]
+    fn disassemble_all_operands() {
+        round_trip(
+"
+.equ SIZE 4
+        ADD IO0 #-3 R1
+        ST R1 [#2]
+        LD [R1] IO2
+loop:   SUB R1 #0x1 R1
+        CMP R1 #0
+        BGT loop
+        JSR sub
+        PUSH #'a'
+        POP RT
+        NOT R2 IO1
+        HLT
+sub:    MOD PC SIZE R2
+        RET
+");
+    }
+
+    #[test]
+    fn disassemble_lables() {
+        let lines = get_program_instructions(
+"
+start: ADD R1 #1 R1
+       CMP R1 #3
+       BLT start
+       BRC start
+").unwrap();
+        assert!(disassemble(&lines) ==
+"l0: ADD R1 #1 R1
+    CMP R1 #3
+    BLT l0
+    BRC l0");
+    }
+}
@@ -0,0 +1,190 @@
+//! Rewrites code in a consistent layout without changing what it assembles to
+
+use super::assembler::{tokenise_with_comment, get_instruction};
+
+/// spaces before each instruction
+const INDENT : usize = 4;
+
+enum FormatLine {
+    Blank,
+    Comment { indented : bool, text : String },
+    Lable(String),
+    Directive(Vec<String>),
+    Code(Vec<String>),
+}
+
+/// registers are uppercase, anything else like numbers and names are left as they are
+fn format_operand(text : &str) -> String {
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return format!("[{}]", format_operand(inner));
+    }
+    let upper = text.to_uppercase();
+    let is_io = upper.strip_prefix("IO").is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    if is_io || ["PC", "R1", "R2", "RT"].contains(&upper.as_str()) {
+        upper
+    } else {
+        text.to_string()
+    }
+}
+
+/// Formats code so that
+/// - lables are on their own line and instructions are indented
+/// - instructions and registers are uppercase, directives are lowercase
+/// - operands line up in columns
+/// - comments are kept, with one space between them and any code
+///
+/// formatting already formatted code doesn't change it
+pub fn format_code(code : &str) -> String {
+    format_code_lines(code).0
+}
+
+/// formats code like `format_code`, also giving the line each line of `code` ends up on.
+/// a line split into lables and an instruction is given the line of the instruction
+pub fn format_code_lines(code : &str) -> (String, Vec<usize>) {
+    let mut lines = Vec::new();
+    //comment of each line, after the line at the same index in `lines`
+    let mut comments : Vec<Option<String>> = Vec::new();
+    let mut new_lines = Vec::new();
+
+    for (line_index, l) in code.split('\n').enumerate() {
+        let (tokens, comment_start) = tokenise_with_comment(l, line_index);
+        new_lines.push(lines.len());
+        let comment = comment_start.map(|i| l[i..].trim_end().to_string());
+        if tokens.is_empty() {
+            lines.push(match comment {
+                Some(text) => FormatLine::Comment { indented : l.starts_with(char::is_whitespace), text },
+                None => FormatLine::Blank,
+            });
+            comments.push(None);
+            continue;
+        }
+
+        if tokens[0].text.starts_with('.') {
+            let mut words : Vec<String> = tokens.iter().map(|t| t.text.clone()).collect();
+            words[0] = words[0].to_lowercase();
+            lines.push(FormatLine::Directive(words));
+            comments.push(comment);
+            continue;
+        }
+
+        let lable_count = tokens.iter().take_while(|t| t.text.ends_with(':')).count();
+        for t in &tokens[..lable_count] {
+            lines.push(FormatLine::Lable(t.text.clone()));
+            comments.push(None);
+        }
+        let mut words : Vec<String> = Vec::new();
+        for (i, t) in tokens[lable_count..].iter().enumerate() {
+            words.push(match i {
+                0 if get_instruction(&t.text).is_ok() => t.text.to_uppercase(),
+                0 => t.text.clone(),
+                _ => format_operand(&t.text),
+            });
+        }
+        if words.is_empty() {
+            //a comment after lables goes on the last of them
+            if let Some(c) = comments.last_mut() {
+                *c = comment;
+            }
+        } else {
+            lines.push(FormatLine::Code(words));
+            comments.push(comment);
+        }
+        if let Some(new_line) = new_lines.last_mut() {
+            *new_line = lines.len() - 1;
+        }
+    }
+
+    //width of each column of instructions and operands
+    let mut widths : Vec<usize> = Vec::new();
+    for line in &lines {
+        if let FormatLine::Code(words) = line {
+            for (i, w) in words.iter().enumerate() {
+                match widths.get_mut(i) {
+                    Some(width) => *width = (*width).max(w.len()),
+                    None => widths.push(w.len()),
+                }
+            }
+        }
+    }
+
+    let mut formatted = Vec::new();
+    for (line, comment) in lines.iter().zip(comments) {
+        let mut text = match line {
+            FormatLine::Blank => String::new(),
+            FormatLine::Comment { indented : true, text } => format!("{}{}", " ".repeat(INDENT), text),
+            FormatLine::Comment { indented : false, text } => text.clone(),
+            FormatLine::Lable(lable) => lable.clone(),
+            FormatLine::Directive(words) => words.join(" "),
+            FormatLine::Code(words) => {
+                let mut text = " ".repeat(INDENT);
+                for (w, width) in words.iter().zip(widths.iter()) {
+                    text.push_str(&format!("{:width$} ", w, width = width));
+                }
+                text
+            },
+        };
+        if let Some(comment) = comment {
+            text = format!("{} {}", text.trim_end(), comment);
+        }
+        formatted.push(text.trim_end().to_string());
+    }
+    (formatted.join("\n"), new_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assembler::get_program_instructions;
+
+    const MESSY : &str =
+"; count down from io0
+.EQU step #1
+start: add io0 #0 r1 ; read input
+loop:
+  sub r1 step r1
+      cmp   R1 #0
+   bgt loop
+	st r1 [r2]  ; save it
+  push #';'
+  ; done
+end: done: hlt";
+
+    #[test]
+    fn format_layout() {
+        assert!(format_code(MESSY) ==
+"; count down from io0
+.equ step #1
+start:
+    ADD  IO0  #0   R1 ; read input
+loop:
+    SUB  R1   step R1
+    CMP  R1   #0
+    BGT  loop
+    ST   R1   [R2] ; save it
+    PUSH #';'
+    ; done
+end:
+done:
+    HLT");
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        let once = format_code(MESSY);
+        assert!(format_code(&once) == once);
+    }
+
+    #[test]
+    fn format_line_map() {
+        let (_, new_lines) = format_code_lines(MESSY);
+        //`start: add` and `end: done: hlt` are split so the lines after them move down
+        assert!(new_lines == vec![0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 13], "{:?}", new_lines);
+    }
+
+    #[test]
+    fn format_keeps_program() {
+        let before = get_program_instructions(MESSY).unwrap();
+        let after = get_program_instructions(&format_code(MESSY)).unwrap();
+        assert!(format!("{:?}", before) == format!("{:?}", after));
+    }
+}
@@ -0,0 +1,197 @@
+//! Expands `.macro` definitions in code into the lines the `assembler` parses
+
+use super::assembler::{tokenise, valid_name, Token, Span, CodeError, ErrorKind};
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// most lines macros can expand to, so macros that use each other many times can't hang the assembler
+const MAX_EXPANDED_LINES : usize = 4096;
+
+/// A line of `Token`s with any macros expanded
+pub struct SourceLine {
+    pub tokens : Vec<Token>,
+    /// `Span` of the macro name where the line was expanded from
+    pub expanded_from : Option<Span>,
+}
+
+struct Macro {
+    params : Vec<String>,
+    body : Vec<Vec<Token>>,
+}
+
+/// lables in a macro are renamed to `lable@N` for its Nth use, so code can't have a lable with
+/// `@` in it. a value such as `#'@'` can still use it
+fn uses_expansion_char(text : &str) -> bool {
+    let text = text.strip_prefix('[').unwrap_or(text);
+    !text.starts_with('#') && text.contains('@')
+}
+
+fn directive(tokens : &[Token]) -> Option<String> {
+    tokens.first()
+        .filter(|t| t.text.starts_with('.'))
+        .map(|t| t.text.to_lowercase())
+}
+
+/// Removes `.macro name params ...` to `.endm` definitions from the code and replaces
+/// every use of a macro with its body, where the params are replaced by the arguments.
+/// Lables defined inside a macro are renamed so each expansion gets its own
+pub fn expand_macros(program_code : &str) -> (Vec<SourceLine>, Vec<CodeError>) {
+    let mut errors = Vec::new();
+    let mut macros : HashMap<String, Macro> = HashMap::new();
+    let mut lines : Vec<Vec<Token>> = Vec::new();
+
+    let mut definition : Option<(Token, Macro)> = None;
+    for (line_index, l) in program_code.split('\n').enumerate() {
+        let tokens = tokenise(l, line_index);
+        let bad_lables : Vec<Span> = tokens.iter().filter(|t| uses_expansion_char(&t.text)).map(|t| t.span).collect();
+        if !bad_lables.is_empty() {
+            errors.extend(bad_lables.into_iter().map(|span| CodeError::new(ErrorKind::MisformedLable, span)));
+            //a line outside a macro is left out so its lables can't clash with the ones in macros,
+            //a line in a macro body is kept like the rest of it
+            if directive(&tokens).is_none() && definition.is_none() {
+                continue;
+            }
+        }
+        match directive(&tokens).as_deref() {
+            Some(".macro") => {
+                if definition.is_some() {
+                    errors.push(CodeError::new(ErrorKind::NestedMacro, tokens[0].span));
+                    continue;
+                }
+                if tokens.len() < 2 {
+                    errors.push(CodeError::new(ErrorKind::TooFewOps, tokens[0].span));
+                    continue;
+                }
+                let name = tokens[1].clone();
+                let mut params : Vec<String> = Vec::new();
+                for p in tokens.iter().skip(2) {
+                    if !valid_name(&p.text) {
+                        errors.push(CodeError::new(ErrorKind::InvalidName, p.span));
+                    } else if params.contains(&p.text) {
+                        errors.push(CodeError::new(ErrorKind::DuplicateName(p.text.clone()), p.span));
+                    }
+                    params.push(p.text.clone());
+                }
+                definition = Some((name, Macro { params, body : Vec::new() }));
+            },
+            Some(".endm") => {
+                match definition.take() {
+                    Some((name, m)) => {
+                        if !valid_name(&name.text) {
+                            errors.push(CodeError::new(ErrorKind::InvalidName, name.span));
+                            continue;
+                        }
+                        match macros.entry(name.text.to_lowercase()) {
+                            Entry::Occupied(_) => errors.push(CodeError::new(ErrorKind::DuplicateName(name.text.clone()), name.span)),
+                            Entry::Vacant(entry) => { entry.insert(m); },
+                        }
+                    },
+                    None => errors.push(CodeError::new(ErrorKind::UnmatchedEndm, tokens[0].span)),
+                }
+            },
+            _ => match definition.as_mut() {
+                Some((_, m)) => m.body.push(tokens),
+                None => lines.push(tokens),
+            },
+        }
+    }
+    if let Some((name, _)) = definition {
+        errors.push(CodeError::new(ErrorKind::UnterminatedMacro, name.span));
+    }
+
+    let mut expanded = Vec::new();
+    let mut expansion_count = 0;
+    for tokens in lines {
+        let result = expand_line(tokens, None, &macros, &mut Vec::new(), &mut expansion_count, &mut expanded, &mut errors);
+        //every macro used after this would be too long as well
+        if let Err(ErrorKind::MacroTooLong(_)) = result {
+            break;
+        }
+    }
+    (expanded, errors)
+}
+
+/// expands a line into `expanded`, `stack` is the names of the macros it's in.
+/// if the expansion has to stop its error is added to `errors` and returned
+fn expand_line(
+    tokens : Vec<Token>,
+    expanded_from : Option<Span>,
+    macros : &HashMap<String, Macro>,
+    stack : &mut Vec<String>,
+    expansion_count : &mut usize,
+    expanded : &mut Vec<SourceLine>,
+    errors : &mut Vec<CodeError>,
+) -> Result<(), ErrorKind> {
+    if let Some(call_span) = expanded_from.filter(|_| expanded.len() >= MAX_EXPANDED_LINES) {
+        let kind = ErrorKind::MacroTooLong(MAX_EXPANDED_LINES);
+        errors.push(CodeError::new(kind.clone(), call_span));
+        return Err(kind);
+    }
+    //lables before the macro name stay on their own line
+    let name_index = tokens.iter().position(|t| !t.text.ends_with(':'));
+    let Some((name_index, m)) = name_index.and_then(|i| Some((i, macros.get(&tokens[i].text.to_lowercase())?))) else {
+        expanded.push(SourceLine { tokens, expanded_from });
+        return Ok(());
+    };
+    let name = &tokens[name_index];
+    let call_span = expanded_from.unwrap_or(name.span);
+    if name_index > 0 {
+        expanded.push(SourceLine { tokens : tokens[..name_index].to_vec(), expanded_from });
+    }
+
+    let args = &tokens[name_index + 1..];
+    if args.len() != m.params.len() {
+        errors.push(CodeError::new(ErrorKind::WrongArgCount(m.params.len()), name.span).expanded_from(expanded_from));
+        return Ok(());
+    }
+    let lowercase_name = name.text.to_lowercase();
+    if stack.contains(&lowercase_name) {
+        let kind = ErrorKind::RecursiveMacro(name.text.clone());
+        errors.push(CodeError::new(kind.clone(), name.span).expanded_from(expanded_from));
+        return Err(kind);
+    }
+
+    *expansion_count += 1;
+    let expansion_id = *expansion_count;
+    let local_lables : Vec<&str> = m.body.iter()
+        .flat_map(|l| l.iter())
+        .filter_map(|t| t.text.strip_suffix(':'))
+        .collect();
+    let rename = |text : &str| -> Option<String> {
+        let (lable, colon) = match text.strip_suffix(':') {
+            Some(l) => (l, ":"),
+            None => (text, ""),
+        };
+        if local_lables.contains(&lable) {
+            Some(format!("{}@{}{}", lable, expansion_id, colon))
+        } else {
+            None
+        }
+    };
+
+    for body_line in m.body.iter() {
+        let line_tokens = body_line.iter().map(|t| {
+            //params can also be used as memory operands, e.g. `[addr]`
+            let inner = t.text.strip_prefix('[')
+                .and_then(|s| s.strip_suffix(']'));
+            let param = |text : &str| m.params.iter().position(|p| p == text);
+            match (param(&t.text), inner.and_then(param)) {
+                (Some(i), _) => args[i].clone(),
+                (None, Some(i)) => Token {
+                    text : format!("[{}]", args[i].text),
+                    span : args[i].span,
+                },
+                (None, None) => Token {
+                    text : rename(&t.text).unwrap_or_else(|| t.text.clone()),
+                    span : t.span,
+                },
+            }
+        }).collect();
+        stack.push(lowercase_name.clone());
+        let result = expand_line(line_tokens, Some(call_span), macros, stack, expansion_count, expanded, errors);
+        stack.pop();
+        result?;
+    }
+    Ok(())
+}
@@ -1,37 +1,55 @@
-//! Holds a `Program` and `CodeWindow` for inputting, drawing and executing user code
+//! Holds the code of a microcontroller and the `Program` assembled from it
 
-mod assembler;
-mod code_window;
-mod program;
+pub mod assembler;
+pub mod breakpoint;
+pub mod disassembler;
+pub mod formatter;
+pub mod model;
+pub mod object;
+mod macros;
+pub mod program;
 
-use self::assembler::CodeError;
-use self::code_window::CodeWindow;
-use self::program::Program;
+use self::program::{Program, ProgramSnapshot};
 
-use crate::geometry::*;
-use crate::resource::Font;
-use crate::input::Typing;
-use crate::FontManager;
-use crate::GameObject;
+pub use self::assembler::{CodeError, ErrorKind, CodeWarning, WarningKind, Span};
+pub use self::breakpoint::Breakpoint;
+pub use self::model::McModel;
+pub use self::program::{OverflowMode, Fault, ExecStats, DEFAULT_MEMORY_SIZE};
 
-use sdl2::video::Window;
-use sdl2::render::Canvas;
+/// The state of a running `Microcontroller`, to go back to with `Microcontroller::restore`
+#[derive(Clone, Debug, PartialEq)]
+pub struct McSnapshot {
+    program : ProgramSnapshot,
+    breakpoint_hit : Option<Breakpoint>,
+}
 
-/// has an interface for updating an drawing a `CodeWindow` and executing a `Program`
-pub struct Microcontroller<'a> {
-    game_obj : GameObject,
-    code_window : CodeWindow<'a>,
+/// has an interface for assembling code and executing the `Program`
+pub struct Microcontroller {
+    code : String,
     program : Program,
+    overflow_mode : OverflowMode,
+    model : &'static McModel,
+    /// source line of each `Line` in the `Program`
+    source_lines : Vec<usize>,
+    /// code of the source line of each `Line` in the `Program`, as it was compiled
+    source_code : Vec<String>,
+    breakpoints : Vec<Breakpoint>,
+    breakpoint_hit : Option<Breakpoint>,
 }
 
-impl<'a> Microcontroller<'a> {
+impl Microcontroller {
 
-    /// make a new mc at a location with a font for rendering the `CodeWindow`
-    pub fn new(game_obj : GameObject, font : Font) -> Self {
+    /// make a new mc of a model with no code
+    pub fn new(model : &'static McModel) -> Self {
         Microcontroller {
-            game_obj,
-            code_window : CodeWindow::new(font, Vec2::new(20.0, 10.0)),
+            code : String::new(),
             program : Program::blank(),
+            overflow_mode : OverflowMode::Wrap,
+            model,
+            source_lines : Vec::new(),
+            source_code : Vec::new(),
+            breakpoints : Vec::new(),
+            breakpoint_hit : None,
         }
     }
 
@@ -39,45 +57,199 @@ impl<'a> Microcontroller<'a> {
     pub fn io_count(&self) -> usize {
         self.program.io_reg_count()
     }
-    
-/// draw the `CodeWindow` to the sdl2 `Canvas`
-    pub fn draw<T>(&mut self, canvas: &mut Canvas<Window>, font_manager : &'a FontManager<T>) -> Result<(), String> {
-        self.code_window.set_draw_lines(font_manager)?;
-        for l in self.code_window.get_draw_code() {
-            canvas.copy(&l.tex, None, l.rect)?;
+
+/// Run the `assembler` on the code and store as a `Program`,
+/// returns any warnings about code that was assembled, the code has to fit in the `McModel`
+    pub fn compile(&mut self) -> Result<Vec<CodeWarning>, Vec<CodeError>> {
+        let assembly = assembler::assemble_for(&self.code, self.model)?;
+        self.program = Program::from_lines(assembly.lines, self.overflow_mode, self.model.memory_size);
+        let code_lines : Vec<&str> = self.code.split('\n').collect();
+        self.source_code = assembly.source_lines.iter()
+            .map(|l| code_lines.get(*l).map_or(String::new(), |code| code.trim().to_string()))
+            .collect();
+        self.source_lines = assembly.source_lines;
+        self.breakpoint_hit = None;
+        Ok(assembly.warnings)
+    }
+
+    /// the compiled `Program` in the `object` format, to run later with `load_object`
+    pub fn object(&self) -> Result<Vec<u8>, object::ObjectError> {
+        object::encode(self.program.lines())
+    }
+
+    /// run a `Program` from the `object` format instead of compiling the code,
+    /// it has to fit in the `McModel`. line breakpoints can't be hit as there is no source
+    pub fn load_object(&mut self, bytes : &[u8]) -> Result<(), String> {
+        let lines = object::decode(bytes).map_err(|e| e.to_string())?;
+        if lines.len() > self.model.max_instructions {
+            return Err(ErrorKind::TooManyInstructions(self.model.max_instructions).to_string());
         }
+        self.program = Program::from_lines(lines, self.overflow_mode, self.model.memory_size);
+        self.source_lines = Vec::new();
+        self.source_code = Vec::new();
+        self.breakpoint_hit = None;
         Ok(())
     }
 
-    pub fn get_game_object(&self) -> &GameObject {
-        &self.game_obj
+    /// set how overflowing maths is handled, takes effect on the next `compile`
+    pub fn set_overflow_mode(&mut self, mode : OverflowMode) {
+        self.overflow_mode = mode;
+    }
+
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
+
+    /// change the model, its limits take effect on the next `compile`
+    pub fn set_model(&mut self, model : &'static McModel) {
+        self.model = model;
+    }
+
+    pub fn model(&self) -> &'static McModel {
+        self.model
+    }
+
+    /// value of a register in the `Program`
+    pub fn get_register_value(&self, reg : assembler::Register) -> Option<i16> {
+        self.program.get_register_value(reg)
     }
-/// Update the `CodeWindow` with user input
-    pub fn update(&mut self, frame_elapsed : f64, typing : &mut Typing) {
-        self.code_window.update(frame_elapsed, typing)
+
+    /// the assembled `Line` at index `i` of the `Program`
+    pub fn line(&self, i : usize) -> Option<assembler::Line> {
+        self.program.line(i)
     }
-/// Run the `assembler` on the code inputted to the `CodeWindow` and store as a `Program`    
-    pub fn compile(&mut self) -> Result<(), CodeError> {
-        Ok(self.program = Program::new(self.code_window.get_code())?)
+
+    /// `Some` if the `Program` was stopped by a `Fault`
+    pub fn fault(&self) -> Option<Fault> {
+        self.program.fault()
     }
 
-    /// get current code stored in `CodeWindow`
+    /// get the code, which is only assembled on `compile`
     pub fn get_code(&self) -> &str {
-        self.code_window.get_code()
+        &self.code
     }
 
-    /// set current code stored in [`CodeWindow`]
     pub fn set_code(&mut self, code : String) {
-        self.code_window.set_code(code);
+        self.code = code;
+    }
+
+    /// excute the next instruction in the `Program`, `false` if it couldn't
+    pub fn step(&mut self) -> bool {
+        if self.breakpoints.is_empty() {
+            self.breakpoint_hit = None;
+            return self.program.step();
+        }
+        //a line is hit by the step that starts one of its instructions, not while waiting on it
+        let starting_line = if self.program.starting_instruction() { self.source_line() } else { None };
+        let line_hit = self.breakpoints.iter()
+            .find(|bp| matches!(bp, Breakpoint::Line(line) if Some(*line) == starting_line))
+            .copied();
+        let before : Vec<bool> = self.breakpoints.iter().map(|bp| self.breakpoint_true(bp)).collect();
+        let progressed = self.program.step();
+        self.breakpoint_hit = line_hit.or_else(|| self.breakpoints.iter()
+            .zip(before)
+            .find(|(bp, before)| !before && self.breakpoint_true(bp))
+            .map(|(bp, _)| *bp));
+        progressed
+    }
+
+    /// `false` for a `Breakpoint::Line`, which is checked as an instruction starts
+    fn breakpoint_true(&self, bp : &Breakpoint) -> bool {
+        match *bp {
+            Breakpoint::Line(_) => false,
+            Breakpoint::RegisterEquals(reg, value) => self.get_register_value(reg) == Some(value),
+            Breakpoint::IoWritten(io) => self.io_read_out_ready(io),
+        }
+    }
+
+    /// save the state of the running `Program` to `restore` later,
+    /// the code and breakpoints aren't saved
+    pub fn snapshot(&self) -> McSnapshot {
+        McSnapshot {
+            program : self.program.snapshot(),
+            breakpoint_hit : self.breakpoint_hit,
+        }
+    }
+
+    /// go back to a state from `snapshot`, only valid until the code is next compiled
+    pub fn restore(&mut self, snapshot : &McSnapshot) {
+        self.program.restore(&snapshot.program);
+        self.breakpoint_hit = snapshot.breakpoint_hit;
     }
 
-    /// excute the next instruction in the `Program`
-    pub fn step(&mut self) {
-        self.program.step();
+    /// the source line of the next instruction to run, `None` if there isn't one
+    pub fn source_line(&self) -> Option<usize> {
+        self.source_lines.get(self.running_line()?).copied()
     }
 
-    /// read value to io register at index
-    pub fn io_read_in(&mut self, value : i16, index : usize) -> Result<(), ()>  {
+    /// the code of `source_line` as it was when compiled, the code may have been edited since
+    pub fn source_line_code(&self) -> Option<&str> {
+        self.source_code.get(self.running_line()?).map(|code| code.as_str())
+    }
+
+    /// index of the `Line` being run, `None` once stopped
+    fn running_line(&self) -> Option<usize> {
+        if self.stopped() {
+            return None;
+        }
+        let pc = self.get_register_value(assembler::Register::PC)?;
+        usize::try_from(pc).ok()
+    }
+
+    /// add a `Breakpoint` if it isn't already set
+    pub fn add_breakpoint(&mut self, bp : Breakpoint) {
+        if !self.breakpoints.contains(&bp) {
+            self.breakpoints.push(bp);
+        }
+    }
+
+    /// `false` if the `Breakpoint` wasn't set
+    pub fn remove_breakpoint(&mut self, bp : &Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != bp);
+        self.breakpoints.len() != len
+    }
+
+    /// replace every `Breakpoint::Line` with breakpoints on `lines`, keeping the others
+    pub fn set_line_breakpoints(&mut self, lines : &[usize]) {
+        self.breakpoints.retain(|b| !matches!(b, Breakpoint::Line(_)));
+        self.breakpoints.extend(lines.iter().map(|l| Breakpoint::Line(*l)));
+    }
+
+    /// replace every breakpoint that isn't a `Breakpoint::Line` with `conditions`
+    pub fn set_condition_breakpoints(&mut self, conditions : &[Breakpoint]) {
+        self.breakpoints.retain(|b| matches!(b, Breakpoint::Line(_)));
+        for bp in conditions {
+            self.add_breakpoint(*bp);
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// the `Breakpoint` hit by the last step, if any
+    pub fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        self.breakpoint_hit
+    }
+
+    pub fn halted(&self) -> bool {
+        self.program.halted()
+    }
+
+    /// how many instructions ran and how many steps were spent blocked or stopped,
+    /// reset when compiled
+    pub fn stats(&self) -> ExecStats {
+        self.program.stats()
+    }
+
+    /// `true` once the `Program` has halted or faulted
+    pub fn stopped(&self) -> bool {
+        self.program.halted() || self.program.fault().is_some()
+    }
+
+    /// read value to io register at index, `false` if it isn't waiting for one
+    pub fn io_read_in(&mut self, value : i16, index : usize) -> bool {
         self.program.read_in(value, index)
     }
 
@@ -94,12 +266,19 @@ impl<'a> Microcontroller<'a> {
         self.program.read_out_ready(index)
     }
 
+    /// the last value written to or read in to an io register
+    pub fn io_value(&self, index : usize) -> i16 {
+        self.program.io_value(index)
+    }
+
     /// debug function to show value in registers
     #[cfg(debug_assertions)]
     pub fn debug_print_registers(&self) {
 
         if self.program.halted() {
             println!("Program Halted\n");
+        } else if let Some(fault) = self.program.fault() {
+            println!("Program Faulted: {}\n", fault);
         } else {
             println!("\nPC: {}", self.program.get_register_value(assembler::Register::PC).unwrap());
             println!("R1: {}", self.program.get_register_value(assembler::Register::R1).unwrap());
@@ -0,0 +1,69 @@
+//! Specs of the microcontrollers that can be placed in a circuit
+
+use super::assembler::Register;
+use super::program::DEFAULT_MEMORY_SIZE;
+
+/// The limits of a microcontroller, enforced when its code is compiled
+#[derive(Debug, PartialEq)]
+pub struct McModel {
+    pub name : &'static str,
+    /// most lines of code, including comments and blank lines
+    pub max_source_lines : usize,
+    /// most instructions after assembling
+    pub max_instructions : usize,
+    /// how many of `R1` and `R2` can be used
+    pub general_registers : usize,
+    /// words of data memory
+    pub memory_size : usize,
+}
+
+/// every model that can be placed, the first is the default
+pub static MODELS : [McModel ; 3] = [
+    McModel {
+        name : "ZL001",
+        max_source_lines : 64,
+        max_instructions : 32,
+        general_registers : 2,
+        memory_size : DEFAULT_MEMORY_SIZE,
+    },
+    McModel {
+        name : "ZL001S",
+        max_source_lines : 24,
+        max_instructions : 12,
+        general_registers : 1,
+        memory_size : 4,
+    },
+    McModel {
+        name : "ZL002",
+        max_source_lines : 256,
+        max_instructions : 128,
+        general_registers : 2,
+        memory_size : 64,
+    },
+];
+
+impl McModel {
+    pub fn default_model() -> &'static McModel {
+        &MODELS[0]
+    }
+
+    /// find a model by its name, ignoring case
+    pub fn from_name(name : &str) -> Option<&'static McModel> {
+        MODELS.iter().find(|m| m.name.eq_ignore_ascii_case(name))
+    }
+
+    /// the model after this one in `MODELS`, wrapping around to the first
+    pub fn next(&self) -> &'static McModel {
+        let i = MODELS.iter().position(|m| m == self).unwrap_or(0);
+        &MODELS[(i + 1) % MODELS.len()]
+    }
+
+    /// `false` for general registers past `general_registers`, others are always available
+    pub fn has_register(&self, reg : Register) -> bool {
+        match reg {
+            Register::R1 => self.general_registers >= 1,
+            Register::R2 => self.general_registers >= 2,
+            _ => true,
+        }
+    }
+}
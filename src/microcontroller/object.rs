@@ -0,0 +1,281 @@
+//! A binary encoding of assembled `Line`s so a program can be stored without its code
+//!
+//! layout, all numbers are little endian
+//! - magic `ZL01`, version byte, `u16` line count
+//! - each line: opcode byte, then 3 operands each a tag byte and its payload
+//! - `u32` checksum of everything before it
+
+use super::assembler::{Instruction, Register, Address, Operand, Line, IO_REGISTER_COUNT};
+
+use std::fmt;
+
+const MAGIC : &[u8; 4] = b"ZL01";
+/// increase when the layout changes
+pub const OBJECT_VERSION : u8 = 1;
+
+/// index of an `Instruction` is its opcode, only add to the end so old objects still load
+const OPCODES : [Instruction; 24] = [
+    Instruction::ADD, Instruction::SUB, Instruction::MUL, Instruction::DIV, Instruction::MOD,
+    Instruction::AND, Instruction::OR, Instruction::XOR, Instruction::NOT, Instruction::SHL,
+    Instruction::SHR, Instruction::CMP, Instruction::BRC, Instruction::BEQ, Instruction::BGT,
+    Instruction::BLT, Instruction::JSR, Instruction::RET, Instruction::PUSH, Instruction::POP,
+    Instruction::LD, Instruction::ST, Instruction::NOP, Instruction::HLT,
+];
+
+const TAG_NONE : u8 = 0;
+const TAG_REG : u8 = 1;
+const TAG_DIRECT : u8 = 2;
+const TAG_MEM_DIRECT : u8 = 3;
+const TAG_MEM_REG : u8 = 4;
+
+/// Why bytes couldn't be decoded into `Line`s
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    BadChecksum,
+    TrailingBytes,
+    UnknownOpcode(u8),
+    UnknownTag(u8),
+    UnknownRegister(u8),
+    /// more lines than the `u16` line count can hold
+    TooManyLines(usize),
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::BadMagic => write!(f, "not a ZL001 object"),
+            ObjectError::UnsupportedVersion(v) => write!(f, "object version {} is not supported", v),
+            ObjectError::Truncated => write!(f, "object ends too early"),
+            ObjectError::BadChecksum => write!(f, "object checksum doesn't match"),
+            ObjectError::TrailingBytes => write!(f, "extra bytes after object"),
+            ObjectError::UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            ObjectError::UnknownTag(tag) => write!(f, "unknown operand tag {}", tag),
+            ObjectError::UnknownRegister(reg) => write!(f, "unknown register {}", reg),
+            ObjectError::TooManyLines(n) => write!(f, "{} lines is more than an object can hold ({})", n, u16::MAX),
+        }
+    }
+}
+
+/// adler-32 of the bytes
+fn checksum(bytes : &[u8]) -> u32 {
+    const MOD : u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    (b << 16) | a
+}
+
+fn opcode(instr : Instruction) -> u8 {
+    OPCODES.iter()
+        .position(|i| *i == instr)
+        .unwrap() as u8
+}
+
+fn register_code(reg : Register) -> u8 {
+    match reg {
+        Register::PC => 0,
+        Register::R1 => 1,
+        Register::R2 => 2,
+        Register::RT => 3,
+        Register::RIO(n) => 4 + n as u8,
+    }
+}
+
+fn register_from_code(code : u8) -> Result<Register, ObjectError> {
+    Ok(match code {
+        0 => Register::PC,
+        1 => Register::R1,
+        2 => Register::R2,
+        3 => Register::RT,
+        n if ((n - 4) as usize) < IO_REGISTER_COUNT => Register::RIO((n - 4) as usize),
+        n => return Err(ObjectError::UnknownRegister(n)),
+    })
+}
+
+fn encode_operand(bytes : &mut Vec<u8>, op : Option<Operand>) {
+    match op {
+        None => bytes.push(TAG_NONE),
+        Some(Operand::Reg(reg)) => bytes.extend([TAG_REG, register_code(reg)]),
+        Some(Operand::Direct(n)) => {
+            bytes.push(TAG_DIRECT);
+            bytes.extend(n.to_le_bytes());
+        },
+        Some(Operand::Mem(Address::Direct(n))) => {
+            bytes.push(TAG_MEM_DIRECT);
+            bytes.extend(n.to_le_bytes());
+        },
+        Some(Operand::Mem(Address::Reg(reg))) => bytes.extend([TAG_MEM_REG, register_code(reg)]),
+    }
+}
+
+/// Encodes `Line`s into the object format, there can be at most `u16::MAX` of them
+pub fn encode(lines : &[Line]) -> Result<Vec<u8>, ObjectError> {
+    let count = u16::try_from(lines.len()).map_err(|_| ObjectError::TooManyLines(lines.len()))?;
+    let mut bytes = Vec::new();
+    bytes.extend(MAGIC);
+    bytes.push(OBJECT_VERSION);
+    bytes.extend(count.to_le_bytes());
+    for line in lines {
+        bytes.push(opcode(line.instr));
+        for op in [line.op1, line.op2, line.op3] {
+            encode_operand(&mut bytes, op);
+        }
+    }
+    bytes.extend(checksum(&bytes).to_le_bytes());
+    Ok(bytes)
+}
+
+/// reads through the bytes of an object
+struct Reader<'a> {
+    bytes : &'a [u8],
+    index : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count : usize) -> Result<&'a [u8], ObjectError> {
+        let taken = self.bytes.get(self.index..self.index + count).ok_or(ObjectError::Truncated)?;
+        self.index += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, ObjectError> {
+        let b = self.take(2)?;
+        Ok(i16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn operand(&mut self) -> Result<Option<Operand>, ObjectError> {
+        Ok(match self.u8()? {
+            TAG_NONE => None,
+            TAG_REG => Some(Operand::Reg(register_from_code(self.u8()?)?)),
+            TAG_DIRECT => Some(Operand::Direct(self.i16()?)),
+            TAG_MEM_DIRECT => Some(Operand::Mem(Address::Direct(self.i16()?))),
+            TAG_MEM_REG => Some(Operand::Mem(Address::Reg(register_from_code(self.u8()?)?))),
+            tag => return Err(ObjectError::UnknownTag(tag)),
+        })
+    }
+}
+
+/// Decodes bytes made by `encode` back into `Line`s
+pub fn decode(bytes : &[u8]) -> Result<Vec<Line>, ObjectError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(ObjectError::BadMagic);
+    }
+    let (body, sum) = bytes.split_at(bytes.len() - 4);
+    let mut reader = Reader { bytes : body, index : MAGIC.len() };
+    let version = reader.u8()?;
+    if version != OBJECT_VERSION {
+        return Err(ObjectError::UnsupportedVersion(version));
+    }
+    if checksum(body).to_le_bytes() != sum {
+        return Err(ObjectError::BadChecksum);
+    }
+
+    let count = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+    let mut lines = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let op = reader.u8()?;
+        let instr = *OPCODES.get(op as usize).ok_or(ObjectError::UnknownOpcode(op))?;
+        lines.push(Line {
+            instr,
+            op1 : reader.operand()?,
+            op2 : reader.operand()?,
+            op3 : reader.operand()?,
+        });
+    }
+    if reader.index != body.len() {
+        return Err(ObjectError::TrailingBytes);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::assembler::get_program_instructions;
+    use super::super::{Microcontroller, McModel};
+
+    const CODE : &str =
+"
+start: ADD IO0 #-300 R1
+       ST R1 [#2]
+       LD [R2] IO3
+       PUSH #0x7FFF
+       POP RT
+       JSR sub
+       BRC start
+sub:   NOT PC R2
+       RET
+";
+
+    #[test]
+    fn object_round_trip() {
+        let lines = get_program_instructions(CODE).unwrap();
+        let bytes = encode(&lines).unwrap();
+        assert!(&bytes[..4] == MAGIC);
+        let decoded = decode(&bytes).unwrap();
+        assert!(lines == decoded);
+        assert!(decode(&encode(&[]).unwrap()).unwrap().is_empty());
+
+        let nop = Line { instr : Instruction::NOP, op1 : None, op2 : None, op3 : None };
+        assert!(decode(&encode(&vec![nop ; u16::MAX as usize]).unwrap()).unwrap().len() == u16::MAX as usize);
+        assert!(encode(&vec![nop ; u16::MAX as usize + 1]) == Err(ObjectError::TooManyLines(u16::MAX as usize + 1)));
+    }
+
+    #[test]
+    fn object_errors() {
+        let bytes = encode(&get_program_instructions(CODE).unwrap()).unwrap();
+
+        assert!(decode(b"ELF\x01") == Err(ObjectError::BadMagic));
+
+        let mut corrupt = bytes.clone();
+        corrupt[10] ^= 0xFF;
+        assert!(decode(&corrupt) == Err(ObjectError::BadChecksum));
+
+        let mut version = bytes.clone();
+        version[4] = 99;
+        assert!(decode(&version) == Err(ObjectError::UnsupportedVersion(99)));
+
+        //a valid checksum over a body that is cut short
+        let mut short = bytes[..bytes.len() - 6].to_vec();
+        short.extend(checksum(&short).to_le_bytes());
+        assert!(decode(&short) == Err(ObjectError::Truncated));
+
+        let mut opcode = bytes[..7].to_vec();
+        opcode[5] = 1;
+        opcode[6] = 0;
+        opcode.push(200);
+        opcode.extend(checksum(&opcode).to_le_bytes());
+        assert!(decode(&opcode) == Err(ObjectError::UnknownOpcode(200)));
+    }
+
+    #[test]
+    fn load_object_into_mc() {
+        let mut mc = Microcontroller::new(McModel::default_model());
+        mc.set_code(String::from("ADD #2 #3 R1\nMUL R1 R1 R1\nHLT"));
+        mc.compile().unwrap();
+        let bytes = mc.object().unwrap();
+
+        let mut rom = Microcontroller::new(McModel::default_model());
+        rom.load_object(&bytes).unwrap();
+        while !rom.stopped() {
+            rom.step();
+        }
+        assert!(rom.get_register_value(Register::R1) == Some(25));
+        assert!(rom.source_line().is_none());
+
+        //the object has to fit in the model it is loaded into
+        let long = encode(&get_program_instructions(&"NOP\n".repeat(20)).unwrap()).unwrap();
+        let mut small = Microcontroller::new(McModel::from_name("ZL001S").unwrap());
+        assert!(small.load_object(&long).is_err());
+        assert!(small.load_object(b"ZL01").is_err());
+    }
+}
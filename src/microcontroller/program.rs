@@ -2,11 +2,13 @@
 
 use crate::microcontroller::assembler::*;
 
+use std::fmt;
+
 const TEST_EQUAL : i16 = 0b001;
 const TEST_LESS_THAN : i16 = 0b010;
 const TEST_GREATER_THAN : i16 = 0b100;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 struct ProgramLineState {
     pub op1 : Option<i16>,
     pub op2 : Option<i16>,
@@ -18,6 +20,118 @@ impl ProgramLineState {
     }
 }
 
+/// How a `Program` handles a maths result that doesn't fit in a register
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverflowMode {
+    /// wrap around the `i16` range
+    Wrap,
+    /// clamp to the `i16` range
+    Saturate,
+    /// clamp to the range -999 to 999
+    SaturateTis,
+    /// stop the program with `Fault::Overflow`
+    Trap,
+}
+
+impl OverflowMode {
+    /// every mode, in the order `next` goes through them
+    pub const ALL : [OverflowMode ; 4] = [OverflowMode::Wrap, OverflowMode::Saturate, OverflowMode::SaturateTis, OverflowMode::Trap];
+
+    /// the name used in `.circ` files
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverflowMode::Wrap => "wrap",
+            OverflowMode::Saturate => "saturate",
+            OverflowMode::SaturateTis => "saturate_tis",
+            OverflowMode::Trap => "trap",
+        }
+    }
+
+    /// find a mode by its name, ignoring case
+    pub fn from_name(name : &str) -> Option<OverflowMode> {
+        OverflowMode::ALL.iter().find(|m| m.name().eq_ignore_ascii_case(name)).copied()
+    }
+
+    /// the mode after this one in `ALL`, wrapping around to the first
+    pub fn next(&self) -> OverflowMode {
+        let i = OverflowMode::ALL.iter().position(|m| m == self).unwrap_or(0);
+        OverflowMode::ALL[(i + 1) % OverflowMode::ALL.len()]
+    }
+}
+
+const TIS_MAX : i32 = 999;
+
+/// Words of data memory a `Program` has unless another size is requested
+pub const DEFAULT_MEMORY_SIZE : usize = 16;
+
+/// Amount of values the hardware stack of a `Program` can hold
+pub const STACK_SIZE : usize = 16;
+
+/// Reason a `Program` stopped executing without halting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fault {
+    Overflow,
+    DivideByZero,
+    PcOutOfRange,
+    InvalidOperand,
+    StackOverflow,
+    StackUnderflow,
+    AddressOutOfRange,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Fault::Overflow => "overflow",
+            Fault::DivideByZero => "divide by zero",
+            Fault::PcOutOfRange => "pc out of range",
+            Fault::InvalidOperand => "invalid operand",
+            Fault::StackOverflow => "stack overflow",
+            Fault::StackUnderflow => "stack underflow",
+            Fault::AddressOutOfRange => "address out of range",
+        })
+    }
+}
+
+/// How the steps of a `Program` were spent, every step counts towards exactly one
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ExecStats {
+    /// instructions that finished
+    pub instructions : usize,
+    /// steps waiting for a value to be read in to an io register
+    pub read_blocked : usize,
+    /// steps waiting for a value written to an io register to be read out
+    pub write_blocked : usize,
+    /// steps after halting or faulting
+    pub stopped : usize,
+}
+
+impl ExecStats {
+    /// every step counted
+    pub fn cycles(&self) -> usize {
+        self.instructions + self.read_blocked + self.write_blocked + self.stopped
+    }
+}
+
+/// Everything about a running `Program` except its code, to go back to with `Program::restore`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgramSnapshot {
+    pc : i16,
+    r1 : i16,
+    r2 : i16,
+    rt : i16,
+    rio : [i16 ; IO_REGISTER_COUNT],
+    stack : Vec<i16>,
+    memory : Vec<i16>,
+    active_io_reg : usize,
+    out_to_read : bool,
+    in_to_read : bool,
+    temp_state : Option<ProgramLineState>,
+    halted : bool,
+    fault : Option<Fault>,
+    stats : ExecStats,
+}
+
 /// Simulates a fake assembly language program, made up of lines of instructions
 pub struct Program {
     code : Vec<Line>,
@@ -26,19 +140,30 @@ pub struct Program {
     r2 : i16,
     rt : i16,
     rio : [i16 ; IO_REGISTER_COUNT],
+    stack : Vec<i16>,
+    memory : Vec<i16>,
     active_io_reg : usize,
     out_to_read : bool,
     in_to_read : bool,
     temp_state : Option<ProgramLineState>,
     halted : bool,
+    overflow : OverflowMode,
+    fault : Option<Fault>,
+    stats : ExecStats,
     //last_line : usize,
 }
 
 impl Program {
-    /// make a program from source code, returns a code error and the line where the error occured if there is a syntax issue
-    pub fn new(program_code : &str) -> Result<Self, CodeError> {
-        Ok(Program {
-            code : get_program_instructions(program_code)?,
+    /// make a program from source code, returns every `CodeError` in the code if there is a syntax issue
+    /// `overflow` decides what happens when a maths instruction overflows,
+    /// `memory_size` is the amount of words available to `LD` and `ST`
+    pub fn new(program_code : &str, overflow : OverflowMode, memory_size : usize) -> Result<Self, Vec<CodeError>> {
+        Ok(Program::from_lines(get_program_instructions(program_code)?, overflow, memory_size))
+    }
+/// make a program from already assembled `Line`s
+    pub fn from_lines(code : Vec<Line>, overflow : OverflowMode, memory_size : usize) -> Self {
+        Program {
+            code,
             pc : 0,
             r1 : 0,
             r2 : 0,
@@ -46,18 +171,24 @@ impl Program {
             active_io_reg : 0,
             out_to_read : false,
             rio : [0 ; IO_REGISTER_COUNT],
+            stack : Vec::with_capacity(STACK_SIZE),
+            memory : vec![0 ; memory_size],
             in_to_read : false,
             temp_state : None,
             halted : false,
+            overflow,
+            fault : None,
+            stats : ExecStats::default(),
             //last_line : 0,
-        })
+        }
     }
 /// return a blank `Program` that is halted
     pub fn blank() -> Self {
         Program {
             code: vec![Line {  instr: Instruction::HLT, op1 : None, op2: None, op3: None}],
             pc: 0, r1: 0, r2: 0, rt: 0, active_io_reg : 0, out_to_read : false,
-            in_to_read : false, rio : [0 ; IO_REGISTER_COUNT], temp_state : None,  halted: true,
+            in_to_read : false, rio : [0 ; IO_REGISTER_COUNT], stack : Vec::new(), memory : Vec::new(), temp_state : None,  halted: true,
+            overflow : OverflowMode::Wrap, fault : None, stats : ExecStats::default(),
         }
     }
     /// get io register count
@@ -75,141 +206,271 @@ impl Program {
             None
         }
     }
+/// the value in an IO register, the last value written to or read in to it
+    pub fn io_value(&self, index : usize) -> i16 {
+        self.rio[index]
+    }
 /// return `true` if there is a value to be read out from IO register
     pub fn read_out_ready(&self, index : usize) -> bool {
         self.out_to_read && self.active_io_reg == index
     }
-/// read in a value to an IO register at that index, if not accepting return `Err`
-    pub fn read_in(&mut self, value : i16, index : usize) -> Result<(), ()> {
+/// read in a value to an IO register at that index, returns `false` if it isn't accepting one
+    pub fn read_in(&mut self, value : i16, index : usize) -> bool {
         assert!(index < IO_REGISTER_COUNT, "io register out of range!");
         if self.in_to_read  && self.active_io_reg == index {
             self.rio[index] = value;
             self.in_to_read = false;
             self.active_io_reg = IO_REGISTER_COUNT;
-            Ok(())
+            true
         } else {
-            Err(())
+            false
         }
     }
 /// return `true` if a value can be read in to an IO register
     pub fn read_in_ready(&self, index : usize) -> bool {
         self.in_to_read && self.active_io_reg == index
     }
-/// step the `Program` forward by one `Line`
-    pub fn step(&mut self) {
-        if self.halted || self.out_to_read || self.in_to_read { return }
-        if self.pc as usize >= self.code.len() {
+/// step the `Program` forward by one `Line`, a `Fault` stops the program on the offending line,
+/// returns `false` if nothing could be done because it's stopped or waiting on an IO register
+    pub fn step(&mut self) -> bool {
+        if self.halted || self.fault.is_some() {
+            self.stats.stopped += 1;
+            return false;
+        }
+        if self.in_to_read {
+            self.stats.read_blocked += 1;
+            return false;
+        }
+        if self.out_to_read {
+            self.stats.write_blocked += 1;
+            return false;
+        }
+        if self.pc < 0 || self.pc as usize > self.code.len() {
+            self.fault = Some(Fault::PcOutOfRange);
+            self.stats.stopped += 1;
+            return true;
+        }
+        if self.pc as usize == self.code.len() {
             self.halted = true;
-            return;
+            self.stats.stopped += 1;
+            return true;
         }
-        
+
+        let line_pc = self.pc;
         let current_line = self.code[self.pc as usize];
         self.pc += 1;
 
+        match self.execute(current_line) {
+            Err(fault) => {
+                self.pc = line_pc;
+                self.fault = Some(fault);
+                self.stats.stopped += 1;
+            },
+            //the instruction has to wait for a value to be read in
+            Ok(()) if self.in_to_read => self.stats.read_blocked += 1,
+            Ok(()) => self.stats.instructions += 1,
+        }
+        true
+    }
+
+    fn execute(&mut self, current_line : Line) -> Result<(), Fault> {
         match current_line.instr {
             Instruction::ADD |
             Instruction::SUB |
             Instruction::MUL |
-            Instruction::DIV => {
-                let (op1, op2) = match self.get_two_ops(current_line) {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return;
-                    }
+            Instruction::DIV |
+            Instruction::MOD => {
+                let (op1, op2) = match self.get_two_ops(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+
+                self.temp_state = None;
+
+                let result = apply_overflow(self.overflow, math_instruction(current_line.instr, op1, op2)?)?;
+                let reg = get_dest_register(current_line.op3)?;
+                self.set_register_value(reg, result);
+            },
+            Instruction::AND |
+            Instruction::OR  |
+            Instruction::XOR |
+            Instruction::SHL |
+            Instruction::SHR => {
+                let (op1, op2) = match self.get_two_ops(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
                 };
 
                 self.temp_state = None;
-                let op1 = op1.unwrap();
-                let op2 = op2.unwrap();
-           
-                let result = math_instruction(current_line.instr, op1, op2);
-                
-                match current_line.op3.unwrap() {
-                    Operand::Reg(reg) => self.set_register_value(reg, result),
-                    _ => panic!("shouldn't be able to assign to non-register"),
-            }},
+
+                let result = bitwise_instruction(current_line.instr, op1, op2);
+                let reg = get_dest_register(current_line.op3)?;
+                self.set_register_value(reg, result);
+            },
+            Instruction::NOT => {
+                let op1 = match self.get_one_op(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+
+                self.temp_state = None;
+
+                let result = !op1;
+                let reg = get_dest_register(current_line.op2)?;
+                self.set_register_value(reg, result);
+            },
             Instruction::CMP => {
-                self.rt = 0;
-                 let (op1, op2) = match self.get_two_ops(current_line) {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return;
-                    }
-                 };
+                let (op1, op2) = match self.get_two_ops(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
 
                 self.temp_state = None;
-                let op1 = op1.unwrap();
-                let op2 = op2.unwrap();
+                self.rt = 0;
 
                 if op1 == op2 { self.rt |= TEST_EQUAL; }
                 if op1 >  op2 { self.rt |= TEST_GREATER_THAN; }
                 if op1 <  op2 { self.rt |= TEST_LESS_THAN; }
             },
             Instruction::BRC => {
-                self.pc = self.get_operand_value(current_line.op1.unwrap()).unwrap();
+                self.pc = self.get_jump_target(current_line)?;
             }
             Instruction::BEQ => {
                 if (self.rt & TEST_EQUAL) != 0 {
-                    self.pc = self.get_operand_value(current_line.op1.unwrap()).unwrap();
+                    self.pc = self.get_jump_target(current_line)?;
                 }
             }
             Instruction::BGT => {
                 if (self.rt & TEST_GREATER_THAN) != 0 {
-                    self.pc = self.get_operand_value(current_line.op1.unwrap()).unwrap();
+                    self.pc = self.get_jump_target(current_line)?;
                 }
             }
             Instruction::BLT => {
                 if (self.rt & TEST_LESS_THAN) != 0 {
-                    self.pc = self.get_operand_value(current_line.op1.unwrap()).unwrap();
+                    self.pc = self.get_jump_target(current_line)?;
                 }
             }
+            Instruction::JSR => {
+                let target = self.get_jump_target(current_line)?;
+                self.push(self.pc)?;
+                self.pc = target;
+            }
+            Instruction::RET => {
+                self.pc = self.pop()?;
+            }
+            Instruction::PUSH => {
+                let op1 = match self.get_one_op(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+
+                self.temp_state = None;
+                self.push(op1)?;
+            },
+            Instruction::POP => {
+                let reg = get_dest_register(current_line.op1)?;
+                let value = self.pop()?;
+                self.set_register_value(reg, value);
+            },
+            Instruction::LD => {
+                let index = self.get_memory_index(current_line.op1)?;
+                let reg = get_dest_register(current_line.op2)?;
+                self.set_register_value(reg, self.memory[index]);
+            },
+            Instruction::ST => {
+                let op1 = match self.get_one_op(current_line)? {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+
+                self.temp_state = None;
+                let index = self.get_memory_index(current_line.op2)?;
+                self.memory[index] = op1;
+            },
             Instruction::HLT => {
                 self.halted = true;
             },
             Instruction::NOP => (),
-       } 
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, value : i16) -> Result<(), Fault> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
     }
 
+    fn pop(&mut self) -> Result<i16, Fault> {
+        self.stack.pop().ok_or(Fault::StackUnderflow)
+    }
+
+    /// memory is only accessed through `get_memory_index`, so a `Mem` operand has no value here
     fn get_operand_value(&self, op : Operand) -> Option<i16> {
         match op {
             Operand::Direct(num) => Some(num),
             Operand::Reg(reg) => self.get_register_value(reg),
+            Operand::Mem(_) => None,
         }
     }
 
-    fn get_two_ops(&mut self, current_line : Line) -> Result<(Option<i16>, Option<i16>), ()> {
-               let mut op1 =  match self.temp_state {
-                    None => self.get_operand_value(current_line.op1.unwrap()),
-                    Some(s) => s.op1,
-               };
-                let mut op2 =  match self.temp_state {
-                    None => self.get_operand_value(current_line.op2.unwrap()),
-                    Some(s) => s.op2,
-                };
+    fn get_memory_index(&self, op : Option<Operand>) -> Result<usize, Fault> {
+        let address = match op {
+            Some(Operand::Mem(Address::Direct(address))) => address,
+            Some(Operand::Mem(Address::Reg(reg))) => self.get_register_value(reg).ok_or(Fault::InvalidOperand)?,
+            _ => return Err(Fault::InvalidOperand),
+        };
+        if address < 0 || address as usize >= self.memory.len() {
+            return Err(Fault::AddressOutOfRange);
+        }
+        Ok(address as usize)
+    }
 
-                if op1.is_none() || op2.is_none() {
-                    if self.temp_state.is_some() {
-                        if op1.is_none() {
-                            
-                            op1 = Some(self.rio[get_io_index(current_line.op1.unwrap())]);
-                        } else {
-                            op2 = Some(self.rio[get_io_index(current_line.op2.unwrap())]);
-                        }
-                    }
-                    
-                    if op1.is_none() || op2.is_none() {
-                        if op1.is_none() {
-                            self.active_io_reg = get_io_index(current_line.op1.unwrap());
-                        } else {
-                             self.active_io_reg = get_io_index(current_line.op2.unwrap());
-                        }
-                        self.in_to_read = true;
-                        self.pc -= 1;
-                        self.temp_state = Some(ProgramLineState::new(op1, op2));
-                        return Err(())
-                    };
+    fn get_jump_target(&self, current_line : Line) -> Result<i16, Fault> {
+        current_line.op1
+            .and_then(|op| self.get_operand_value(op))
+            .ok_or(Fault::InvalidOperand)
+    }
+
+    /// returns `Ok(None)` if the `Program` has to wait for an IO register to be read in
+    fn get_two_ops(&mut self, current_line : Line) -> Result<Option<(i16, i16)>, Fault> {
+        let op1_operand = current_line.op1.ok_or(Fault::InvalidOperand)?;
+        let op2_operand = current_line.op2.ok_or(Fault::InvalidOperand)?;
+        let (mut op1, mut op2) = match self.temp_state {
+            None => (self.get_operand_value(op1_operand), self.get_operand_value(op2_operand)),
+            Some(s) => (s.op1, s.op2),
+        };
+
+        if op1.is_none() || op2.is_none() {
+            if self.temp_state.is_some() {
+                if op1.is_none() {
+                    op1 = Some(self.rio[get_io_index(op1_operand)?]);
+                } else {
+                    op2 = Some(self.rio[get_io_index(op2_operand)?]);
+                }
+            }
+
+            if op1.is_none() || op2.is_none() {
+                if op1.is_none() {
+                    self.active_io_reg = get_io_index(op1_operand)?;
+                } else {
+                    self.active_io_reg = get_io_index(op2_operand)?;
                 }
-        Ok((op1, op2))
+                self.in_to_read = true;
+                self.pc -= 1;
+                self.temp_state = Some(ProgramLineState::new(op1, op2));
+                return Ok(None);
+            }
+        }
+        Ok(op1.zip(op2))
+    }
+
+    /// same as `get_two_ops` for instructions that only read from `op1`
+    fn get_one_op(&mut self, current_line : Line) -> Result<Option<i16>, Fault> {
+        let line = Line { op2 : Some(Operand::Direct(0)), ..current_line };
+        Ok(self.get_two_ops(line)?.map(|(op1, _)| op1))
     }
 /// returns `None` if an IO register is requested
     pub fn get_register_value(&self, reg : Register) -> Option<i16> {
@@ -239,25 +500,321 @@ impl Program {
     pub fn halted(&self) -> bool {
         self.halted
     }
+/// `Some` if the program was stopped by a `Fault`
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+/// save the state of the program to `restore` later
+    pub fn snapshot(&self) -> ProgramSnapshot {
+        ProgramSnapshot {
+            pc : self.pc,
+            r1 : self.r1,
+            r2 : self.r2,
+            rt : self.rt,
+            rio : self.rio,
+            stack : self.stack.clone(),
+            memory : self.memory.clone(),
+            active_io_reg : self.active_io_reg,
+            out_to_read : self.out_to_read,
+            in_to_read : self.in_to_read,
+            temp_state : self.temp_state,
+            halted : self.halted,
+            fault : self.fault,
+            stats : self.stats,
+        }
+    }
+/// go back to a state from `snapshot`, which has to be from a program with the same code
+    pub fn restore(&mut self, snapshot : &ProgramSnapshot) {
+        self.pc = snapshot.pc;
+        self.r1 = snapshot.r1;
+        self.r2 = snapshot.r2;
+        self.rt = snapshot.rt;
+        self.rio = snapshot.rio;
+        self.stack.clone_from(&snapshot.stack);
+        self.memory.clone_from(&snapshot.memory);
+        self.active_io_reg = snapshot.active_io_reg;
+        self.out_to_read = snapshot.out_to_read;
+        self.in_to_read = snapshot.in_to_read;
+        self.temp_state = snapshot.temp_state;
+        self.halted = snapshot.halted;
+        self.fault = snapshot.fault;
+        self.stats = snapshot.stats;
+    }
+/// `true` if the next step starts a new instruction, rather than finishing one waiting on an IO register
+    pub fn starting_instruction(&self) -> bool {
+        !self.halted && self.fault.is_none() && self.temp_state.is_none() && !self.out_to_read
+    }
+/// every `Line` of the code
+    pub fn lines(&self) -> &[Line] {
+        &self.code
+    }
+/// the `Line` at index `i` of the code
+    pub fn line(&self, i : usize) -> Option<Line> {
+        self.code.get(i).copied()
+    }
+/// how the program has spent its steps so far
+    pub fn stats(&self) -> ExecStats {
+        self.stats
+    }
 
    // pub fn get_last_line(&self) -> Line {
    //     self.code[self.last_line].clone()
    // }
 }
 
-fn math_instruction(instr : Instruction, op1 : i16, op2 : i16) -> i16 {
-    match instr {
+/// result is widened to `i32` so overflow can be handled by `apply_overflow`
+fn math_instruction(instr : Instruction, op1 : i16, op2 : i16) -> Result<i32, Fault> {
+    let (op1, op2) = (op1 as i32, op2 as i32);
+    if op2 == 0 && matches!(instr, Instruction::DIV | Instruction::MOD) {
+        return Err(Fault::DivideByZero);
+    }
+    Ok(match instr {
         Instruction::ADD => op1 + op2,
         Instruction::SUB => op1 - op2,
         Instruction::MUL => op1 * op2,
         Instruction::DIV => op1 / op2,
+        Instruction::MOD => op1 % op2,
         _ => panic!("only acccepts math instructions!"),
+    })
+}
+
+/// works on the 16 bits of the values so can't overflow, the `OverflowMode` isn't used.
+/// bits shifted out are lost, `SHR` keeps the sign and a shift of 16 or more clears every bit
+fn bitwise_instruction(instr : Instruction, op1 : i16, op2 : i16) -> i16 {
+    match instr {
+        Instruction::AND => op1 & op2,
+        Instruction::OR  => op1 | op2,
+        Instruction::XOR => op1 ^ op2,
+        Instruction::SHL => op1.checked_shl(op2 as u32).unwrap_or(0),
+        Instruction::SHR => op1.checked_shr(op2 as u32).unwrap_or(if op1 < 0 { -1 } else { 0 }),
+        _ => panic!("only acccepts bitwise instructions!"),
+    }
+}
+
+fn apply_overflow(mode : OverflowMode, value : i32) -> Result<i16, Fault> {
+    match mode {
+        OverflowMode::Wrap => Ok(value as i16),
+        OverflowMode::Saturate => Ok(value.clamp(i16::MIN as i32, i16::MAX as i32) as i16),
+        OverflowMode::SaturateTis => Ok(value.clamp(-TIS_MAX, TIS_MAX) as i16),
+        OverflowMode::Trap => i16::try_from(value).map_err(|_| Fault::Overflow),
+    }
+}
+
+fn get_io_index(op : Operand) -> Result<usize, Fault> {
+    match op {
+        Operand::Reg(Register::RIO(index)) if index < IO_REGISTER_COUNT => Ok(index),
+        _ => Err(Fault::InvalidOperand),
     }
 }
 
-fn get_io_index(op : Operand) -> usize {
+fn get_dest_register(op : Option<Operand>) -> Result<Register, Fault> {
     match op {
-        Operand::Reg(Register::RIO(index)) => index,
-        _ => panic!("tried to get index of non io register!")
+        Some(Operand::Reg(Register::RIO(index))) if index >= IO_REGISTER_COUNT => Err(Fault::InvalidOperand),
+        Some(Operand::Reg(reg)) => Ok(reg),
+        _ => Err(Fault::InvalidOperand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code : &str) -> Program {
+        run_with(code, OverflowMode::Wrap)
+    }
+
+    fn run_with(code : &str, overflow : OverflowMode) -> Program {
+        let mut program = Program::new(code, overflow, DEFAULT_MEMORY_SIZE).unwrap();
+        while !program.halted() && program.fault().is_none() {
+            program.step();
+        }
+        program
+    }
+
+    #[test]
+    fn mod_and_bitwise() {
+        let program = run(
+"
+ADD #7 #0 R1
+MOD R1 #2 R2
+AND R1 #6 R1
+HLT
+");
+        assert!(program.get_register_value(Register::R2) == Some(1));
+        assert!(program.get_register_value(Register::R1) == Some(6));
+
+        let program = run(
+"
+OR #12 #3 R1
+XOR R1 #5 R1
+NOT R1 R2
+HLT
+");
+        assert!(program.get_register_value(Register::R1) == Some(10));
+        assert!(program.get_register_value(Register::R2) == Some(-11));
+    }
+
+    #[test]
+    fn shifts() {
+        let program = run(
+"
+SHL #3 #2 R1
+SUB #0 #8 R2
+SHR R2 #1 R2
+HLT
+");
+        assert!(program.get_register_value(Register::R1) == Some(12));
+        assert!(program.get_register_value(Register::R2) == Some(-4));
+
+        let program = run(
+"
+SHL #1 #16 R1
+SHR #1 #20 R2
+HLT
+");
+        assert!(program.get_register_value(Register::R1) == Some(0));
+        assert!(program.get_register_value(Register::R2) == Some(0));
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let code =
+"
+ADD #30000 #30000 R1
+MUL #-2000 #20 R2
+HLT
+";
+        let program = run_with(code, OverflowMode::Wrap);
+        assert!(program.get_register_value(Register::R1) == Some(-5536));
+        assert!(program.get_register_value(Register::R2) == Some(25536));
+
+        let program = run_with(code, OverflowMode::Saturate);
+        assert!(program.get_register_value(Register::R1) == Some(i16::MAX));
+        assert!(program.get_register_value(Register::R2) == Some(i16::MIN));
+
+        let program = run_with(code, OverflowMode::SaturateTis);
+        assert!(program.get_register_value(Register::R1) == Some(999));
+        assert!(program.get_register_value(Register::R2) == Some(-999));
+
+        let program = run_with(code, OverflowMode::Trap);
+        assert!(program.fault() == Some(Fault::Overflow));
+        assert!(program.get_register_value(Register::R1) == Some(0));
+        assert!(program.get_register_value(Register::PC) == Some(0));
+
+        let program = run_with("DIV #-32768 #-1 R1\nHLT", OverflowMode::Trap);
+        assert!(program.fault() == Some(Fault::Overflow));
+    }
+
+    #[test]
+    fn bitwise_ignores_overflow_mode() {
+        let code =
+"
+OR #1024 #0 R1
+SHL #1 #15 R2
+NOT #-1000 RT
+HLT
+";
+        for mode in [OverflowMode::Wrap, OverflowMode::Saturate, OverflowMode::SaturateTis, OverflowMode::Trap] {
+            let program = run_with(code, mode);
+            assert!(program.fault().is_none(), "{:?}", mode);
+            assert!(program.get_register_value(Register::R1) == Some(1024), "{:?}", mode);
+            assert!(program.get_register_value(Register::R2) == Some(i16::MIN), "{:?}", mode);
+            assert!(program.get_register_value(Register::RT) == Some(999), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn faults() {
+        let program = run("DIV #4 #0 R1\nHLT");
+        assert!(program.fault() == Some(Fault::DivideByZero));
+        assert!(program.get_register_value(Register::PC) == Some(0));
+
+        let program = run("MOD #4 #0 R1\nHLT");
+        assert!(program.fault() == Some(Fault::DivideByZero));
+
+        let program = run("ADD #-1 #0 PC\nHLT");
+        assert!(program.fault() == Some(Fault::PcOutOfRange));
+
+        let program = run("ADD #20 #0 PC\nHLT");
+        assert!(program.fault() == Some(Fault::PcOutOfRange));
+
+        let program = run("ADD #2 #0 PC\nNOP");
+        assert!(program.fault().is_none());
+        assert!(program.halted());
+    }
+
+    #[test]
+    fn stack_and_subroutines() {
+        let program = run(
+"
+PUSH #3
+PUSH #4
+JSR add
+PUSH R1
+POP R2
+HLT
+add:
+POP RT
+POP R2
+POP R1
+ADD R1 R2 R1
+PUSH RT
+RET
+");
+        assert!(program.fault().is_none());
+        assert!(program.get_register_value(Register::R1) == Some(7));
+        assert!(program.get_register_value(Register::R2) == Some(7));
+
+        let program = run("loop:\nJSR loop");
+        assert!(program.fault() == Some(Fault::StackOverflow));
+        assert!(program.get_register_value(Register::PC) == Some(0));
+
+        let program = run("RET");
+        assert!(program.fault() == Some(Fault::StackUnderflow));
+    }
+
+    #[test]
+    fn load_and_store() {
+        let program = run(
+"
+ADD #0 #0 R1
+fill:
+MUL R1 R1 R2
+ST R2 [R1]
+ADD R1 #1 R1
+CMP R1 #16
+BLT fill
+LD [#5] R1
+ADD #3 #0 R2
+LD [R2] R2
+HLT
+");
+        assert!(program.fault().is_none());
+        assert!(program.get_register_value(Register::R1) == Some(25));
+        assert!(program.get_register_value(Register::R2) == Some(9));
+
+        let program = run("ST #1 [#16]");
+        assert!(program.fault() == Some(Fault::AddressOutOfRange));
+
+        let program = run("ADD #-1 #0 R1\nLD [R1] R2");
+        assert!(program.fault() == Some(Fault::AddressOutOfRange));
+    }
+
+    #[test]
+    fn exec_stats() {
+        let mut program = Program::new("ADD IO0 #1 R1\nADD R1 #0 IO2\nHLT", OverflowMode::Wrap, DEFAULT_MEMORY_SIZE).unwrap();
+        program.step();
+        program.step();
+        assert!(program.stats() == ExecStats { read_blocked : 2, ..ExecStats::default() });
+        assert!(program.read_in(4, 0));
+        program.step();
+        program.step();
+        program.step();
+        assert!(program.read_out(2) == Some(5));
+        program.step();
+        program.step();
+        assert!(program.halted());
+        assert!(program.stats() == ExecStats { instructions : 3, read_blocked : 2, write_blocked : 1, stopped : 1 });
+        assert!(program.stats().cycles() == 7);
     }
 }
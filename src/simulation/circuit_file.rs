@@ -0,0 +1,162 @@
+//! Reads and writes `.circ` files
+//!
+//! each microcontroller is `<mc>` then a line of its rect, model name and overflow mode,
+//! then its code. after them is `<connections>` then a line for each connection `mc io mc io`
+
+use super::{Simulation, McConnection};
+use crate::geometry::Rect;
+use crate::microcontroller::{McModel, OverflowMode};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A `Microcontroller` as it is stored in a file
+pub struct SavedMc {
+    pub rect : Rect,
+    pub model : &'static McModel,
+    pub overflow : OverflowMode,
+    pub code : String,
+}
+
+/// The contents of a `.circ` file
+pub struct CircuitFile {
+    pub mcs : Vec<SavedMc>,
+    pub connections : Vec<(McConnection, McConnection)>,
+}
+
+impl CircuitFile {
+    /// save the `Simulation` with the rect each `Microcontroller` is drawn at
+    pub fn new(sim : &Simulation, rects : &[Rect]) -> Self {
+        CircuitFile {
+            mcs : sim.mcs().iter().zip(rects).map(|(mc, rect)| SavedMc {
+                rect : rect.clone(),
+                model : mc.model(),
+                overflow : mc.overflow_mode(),
+                code : mc.get_code().to_string(),
+            }).collect(),
+            connections : sim.connections().iter().map(|(k, v)| (*k, *v)).collect(),
+        }
+    }
+
+    pub fn parse(text : &str) -> Result<Self, String> {
+        let (mc_text, connection_text) = match text.split_once("<connections>") {
+            Some(v) => v,
+            None => { return Err(String::from("error parsing <connections>")); },
+        };
+        let mut mcs = Vec::new();
+        for mc in mc_text.split("<mc>").skip(1) {
+            //get rect
+            let (rect, code) = match mc.trim_start().split_once('\n') {
+                Some(v) => v,
+                None => { return Err(String::from("error parsing rect/code split")); },
+            };
+            //older saves have no model name or overflow mode after the rect
+            let words : Vec<&str> = rect.split_whitespace().collect();
+            let rect = parse_4_vals(&words[..words.len().min(4)].join(" "))?;
+            let model = match words.get(4) {
+                Some(name) => McModel::from_name(name).ok_or(format!("unknown model {}", name))?,
+                None => McModel::default_model(),
+            };
+            let overflow = match words.get(5) {
+                Some(name) => OverflowMode::from_name(name).ok_or(format!("unknown overflow mode {}", name))?,
+                None => OverflowMode::Wrap,
+            };
+            if words.len() > 6 {
+                return Err(format!("unexpected text after the overflow mode: {}", words[6..].join(" ")));
+            }
+            mcs.push(SavedMc {
+                rect : Rect::new(rect[0], rect[1], rect[2], rect[3]),
+                model,
+                overflow,
+                code : code.strip_suffix('\n').unwrap_or(code).to_string(),
+            });
+        }
+
+        let mut connections = Vec::new();
+        for con in connection_text.trim().split('\n') {
+            if !con.is_empty() {
+                let con = parse_4_vals(con)?;
+                connections.push((McConnection::new(con[0], con[1]), McConnection::new(con[2], con[3])));
+            }
+        }
+        Ok(CircuitFile { mcs, connections })
+    }
+
+    pub fn load(path : &Path) -> Result<Self, String> {
+        CircuitFile::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn save(&self, path : &Path) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|e| e.to_string())
+    }
+
+    /// make a `Simulation` of the saved circuit, the code is not compiled
+    pub fn to_simulation(&self) -> Result<Simulation, String> {
+        let mut sim = Simulation::new();
+        for saved in self.mcs.iter() {
+            let i = sim.add_mc(saved.model);
+            sim.mc_mut(i).set_code(saved.code.clone());
+            sim.mc_mut(i).set_overflow_mode(saved.overflow);
+        }
+        for (con1, con2) in self.connections.iter() {
+            sim.add_connection(*con1, *con2)?;
+        }
+        Ok(sim)
+    }
+}
+
+impl fmt::Display for CircuitFile {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        for mc in self.mcs.iter() {
+            writeln!(f, "<mc>\n{} {} {} {} {} {}", mc.rect.x, mc.rect.y, mc.rect.w, mc.rect.h, mc.model.name, mc.overflow.name())?;
+            writeln!(f, "{}", mc.code)?;
+        }
+        writeln!(f, "<connections>")?;
+        for (out_io, in_io) in self.connections.iter() {
+            writeln!(f, "{} {} {} {}", out_io.get_mc_i(), out_io.get_io_i(), in_io.get_mc_i(), in_io.get_io_i())?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_4_vals<T : FromStr>(text : &str) -> Result<Vec::<T>, String> {
+    let vals = text
+        .split(' ')
+        .map(|v| v.trim().parse::<T>().map_err(|_| format!("error parsing str into number [circuit_file::parse_4_vals()], text : {}", v.trim())))
+        .collect::<Result<Vec<T>, String>>()?;
+    if vals.len() != 4 {
+        return Err(String::from("parse wasn't 4"));
+    }
+    Ok(vals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuit_file_round_trip() {
+        let text = "<mc>\n20 50 100 100\nadd io0 io1 r2\nnop\n<mc>\n200 50 80 80 ZL001S trap\nhlt\n<connections>\n0 1 1 3\n";
+        let file = CircuitFile::parse(text).unwrap();
+        assert!(file.mcs.len() == 2);
+        assert!(file.mcs[0].model.name == "ZL001");
+        assert!(file.mcs[0].code == "add io0 io1 r2\nnop");
+        assert!(file.mcs[1].model.name == "ZL001S");
+        assert!(file.mcs[0].overflow == OverflowMode::Wrap);
+        assert!(file.mcs[1].overflow == OverflowMode::Trap);
+        assert!(file.connections == vec![(McConnection::new(0, 1), McConnection::new(1, 3))]);
+
+        let saved = file.to_string();
+        assert!(saved == text.replace("20 50 100 100\n", "20 50 100 100 ZL001 wrap\n"));
+        assert!(CircuitFile::parse(&saved).unwrap().to_string() == saved);
+
+        let sim = file.to_simulation().unwrap();
+        assert!(sim.mcs()[1].get_code() == "hlt");
+        assert!(sim.mcs()[1].overflow_mode() == OverflowMode::Trap);
+
+        assert!(CircuitFile::parse("<mc>\n0 0 50 50 ZL001 clamp\nhlt\n<connections>\n").is_err());
+        assert!(sim.connections().len() == 1);
+    }
+}
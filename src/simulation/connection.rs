@@ -1,4 +1,5 @@
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+/// an io port of a microcontroller, by index of the microcontroller and port
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, Debug)]
 pub struct McConnection {
     mc_i : usize,
     io_i : usize,
@@ -13,7 +14,7 @@ impl McConnection {
     pub fn get_mc_i(&self) -> usize {
         self.mc_i
     }
-    
+
     pub fn get_io_i(&self) -> usize {
         self.io_i
     }
@@ -0,0 +1,658 @@
+//! Runs `Microcontroller`s connected by their io ports, with no dependence on a window
+//! so circuits can be simulated by the game or any other front end
+//!
+//! the circuit runs in cycles, each `Simulation::step` is one cycle made of two phases:
+//! 1. compute, every `Microcontroller` is stepped exactly once. a chip that needs a
+//!    value from an io port stops on that instruction, one writing to a port holds
+//!    the value until it is passed on
+//! 2. transfer, every output port holding a value passes it to the input port it is
+//!    connected to if that port is waiting for it. no chip is stepped here so the
+//!    reading chip finishes its instruction in the compute phase of the next cycle
+//!
+//! chips only see each other through transfers, so the outcome doesn't depend on
+//! the order of the chips
+
+mod connection;
+mod output;
+pub mod circuit_file;
+pub mod trace;
+pub mod vcd;
+
+pub use self::connection::McConnection;
+pub use self::circuit_file::{CircuitFile, SavedMc};
+pub use self::trace::Tracer;
+pub use self::vcd::VcdWriter;
+
+use crate::microcontroller::{Microcontroller, McModel, McSnapshot, CodeWarning, CodeError, Breakpoint};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A value passed from an output port to an input port during a step
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IoTransfer {
+    pub from : McConnection,
+    pub to : McConnection,
+    pub value : i16,
+}
+
+/// Which way a value is waiting to go through a port
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IoDirection {
+    In,
+    Out,
+}
+
+/// A port that a `Microcontroller` is waiting on to finish its instruction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockedPort {
+    pub port : McConnection,
+    pub direction : IoDirection,
+    /// the port at the other end of the connection, `None` if it isn't connected
+    pub partner : Option<McConnection>,
+}
+
+impl fmt::Display for BlockedPort {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let (verb, prep) = match self.direction {
+            IoDirection::In => ("read", "from"),
+            IoDirection::Out => ("write", "to"),
+        };
+        write!(f, "mc {} waiting to {} IO{}", self.port.get_mc_i(), verb, self.port.get_io_i())?;
+        match self.partner {
+            Some(p) => write!(f, " {} mc {} IO{}", prep, p.get_mc_i(), p.get_io_i()),
+            None => write!(f, " (not connected)"),
+        }
+    }
+}
+
+/// Why a running `Simulation` should stop after a step
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Deadlock,
+    Halted,
+}
+
+/// The state of every `Microcontroller` in a running `Simulation`,
+/// to go back to with `Simulation::restore`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimSnapshot {
+    mcs : Vec<McSnapshot>,
+    stalled : bool,
+    cycles : usize,
+    breakpoint_hits : Vec<(usize, Breakpoint)>,
+}
+
+/// A list of `Microcontroller`s and the connections from an output port to an input port
+#[derive(Default)]
+pub struct Simulation {
+    mcs : Vec<Microcontroller>,
+    connections : HashMap<McConnection, McConnection>,
+    stalled : bool,
+    cycles : usize,
+    breakpoint_hits : Vec<(usize, Breakpoint)>,
+    tracer : Option<Tracer>,
+    vcd : Option<VcdWriter>,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Simulation {
+            mcs : Vec::new(),
+            connections : HashMap::new(),
+            stalled : false,
+            cycles : 0,
+            breakpoint_hits : Vec::new(),
+            tracer : None,
+            vcd : None,
+        }
+    }
+
+    /// add a `Microcontroller` with no code, returns its index
+    pub fn add_mc(&mut self, model : &'static McModel) -> usize {
+        self.mcs.push(Microcontroller::new(model));
+        if let Some(vcd) = &mut self.vcd {
+            vcd.add_mc();
+        }
+        self.mcs.len() - 1
+    }
+
+    /// remove a `Microcontroller` and its connections,
+    /// the last `Microcontroller` takes the index of the removed one
+    pub fn remove_mc(&mut self, i : usize) {
+        if i >= self.mcs.len() { return; }
+        let changed_index = self.mcs.len() - 1;
+        let moved = |con : McConnection| {
+            if con.get_mc_i() == changed_index {
+                McConnection::new(i, con.get_io_i())
+            } else {
+                con
+            }
+        };
+        self.connections = self.connections.iter()
+            .filter(|(k, v)| k.get_mc_i() != i && v.get_mc_i() != i)
+            .map(|(k, v)| (moved(*k), moved(*v)))
+            .collect();
+        self.mcs.swap_remove(i);
+        if let Some(vcd) = &mut self.vcd {
+            vcd.remove_mc(i);
+        }
+    }
+
+    /// add a one way connection from `con1` to `con2`
+    pub fn add_connection(&mut self, con1 : McConnection, con2 : McConnection) -> Result<(), String> {
+        if con1.get_mc_i() >= self.mcs.len() || con2.get_mc_i() >= self.mcs.len() {
+            return Err(String::from("connection: mc index out of range"));
+        }
+        if con1.get_io_i() >= self.mcs[con1.get_mc_i()].io_count() || con2.get_io_i() >= self.mcs[con2.get_mc_i()].io_count() {
+            return Err(String::from("connection: io index out of range"));
+        }
+        self.connections.insert(con1, con2);
+        Ok(())
+    }
+
+    /// connect two ports both ways, does nothing if either port is already connected
+    pub fn connect(&mut self, con1 : McConnection, con2 : McConnection) -> Result<(), String> {
+        if !self.connections.contains_key(&con1) && !self.connections.contains_key(&con2) {
+            self.add_connection(con1, con2)?;
+            self.add_connection(con2, con1)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.mcs.clear();
+        self.connections.clear();
+        self.stalled = false;
+        self.cycles = 0;
+        self.breakpoint_hits.clear();
+        if let Some(vcd) = &mut self.vcd {
+            vcd.clear_mcs();
+        }
+    }
+
+    pub fn mcs(&self) -> &[Microcontroller] {
+        &self.mcs
+    }
+
+    pub fn mc_mut(&mut self, i : usize) -> &mut Microcontroller {
+        &mut self.mcs[i]
+    }
+
+    pub fn connections(&self) -> &HashMap<McConnection, McConnection> {
+        &self.connections
+    }
+
+    /// compile every `Microcontroller`, returns the result of each in order
+    pub fn compile(&mut self) -> Vec<Result<Vec<CodeWarning>, Vec<CodeError>>> {
+        self.cycles = 0;
+        self.stalled = false;
+        self.breakpoint_hits.clear();
+        self.mcs.iter_mut().map(|mc| mc.compile()).collect()
+    }
+
+    /// cycles run since the circuit was compiled
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn io_in_ready(&self, connection : &McConnection) -> bool {
+        self.mcs[connection.get_mc_i()].io_read_in_ready(connection.get_io_i())
+    }
+
+    fn io_out_ready(&self, connection : &McConnection) -> bool {
+        self.mcs[connection.get_mc_i()].io_read_out_ready(connection.get_io_i())
+    }
+
+    /// `true` once every `Microcontroller` has halted or faulted
+    pub fn stopped(&self) -> bool {
+        self.mcs.iter().all(|mc| mc.stopped())
+    }
+
+    /// `true` if nothing happened on the last step because every `Microcontroller`
+    /// that hasn't stopped is waiting on io that can't be passed
+    pub fn stalled(&self) -> bool {
+        self.stalled && !self.stopped()
+    }
+
+    /// every port that a `Microcontroller` that hasn't stopped is waiting on
+    pub fn blocked_ports(&self) -> Vec<BlockedPort> {
+        let mut blocked = Vec::new();
+        for (mc_i, mc) in self.mcs.iter().enumerate().filter(|(_, mc)| !mc.stopped()) {
+            for io_i in 0..mc.io_count() {
+                let port = McConnection::new(mc_i, io_i);
+                if mc.io_read_in_ready(io_i) {
+                    let partner = self.connections.iter().find(|(_, to)| **to == port).map(|(from, _)| *from);
+                    blocked.push(BlockedPort { port, direction : IoDirection::In, partner });
+                } else if mc.io_read_out_ready(io_i) {
+                    let partner = self.connections.get(&port).copied();
+                    blocked.push(BlockedPort { port, direction : IoDirection::Out, partner });
+                }
+            }
+        }
+        blocked
+    }
+
+    /// the ports every `Microcontroller` is stuck on if the circuit has stalled,
+    /// it will never get any further so should stop being run
+    pub fn deadlock(&self) -> Option<Vec<BlockedPort>> {
+        if self.stalled() {
+            Some(self.blocked_ports())
+        } else {
+            None
+        }
+    }
+
+    /// save the state of the running circuit to `restore` later,
+    /// the code and connections aren't saved
+    pub fn snapshot(&self) -> SimSnapshot {
+        SimSnapshot {
+            mcs : self.mcs.iter().map(|mc| mc.snapshot()).collect(),
+            stalled : self.stalled,
+            cycles : self.cycles,
+            breakpoint_hits : self.breakpoint_hits.clone(),
+        }
+    }
+
+    /// go back to a state from `snapshot`, only valid until the circuit is changed or compiled
+    pub fn restore(&mut self, snapshot : &SimSnapshot) -> Result<(), String> {
+        if snapshot.mcs.len() != self.mcs.len() {
+            return Err(String::from("snapshot is of a different circuit"));
+        }
+        for (mc, mc_snapshot) in self.mcs.iter_mut().zip(snapshot.mcs.iter()) {
+            mc.restore(mc_snapshot);
+        }
+        self.stalled = snapshot.stalled;
+        self.cycles = snapshot.cycles;
+        self.breakpoint_hits.clone_from(&snapshot.breakpoint_hits);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record_restore(self.cycles);
+        }
+        Ok(())
+    }
+
+    /// record every step from now on, replaces any `Tracer` already set
+    pub fn set_tracer(&mut self, tracer : Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// stop recording, returns the `Tracer` so it can be finished
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    /// write the io ports of every step from now on to a VCD, replaces any `VcdWriter` already set
+    pub fn set_vcd(&mut self, mut vcd : VcdWriter) {
+        vcd.start(&self.mcs);
+        self.vcd = Some(vcd);
+    }
+
+    /// stop writing the VCD, returns the `VcdWriter` so it can be finished
+    pub fn take_vcd(&mut self) -> Option<VcdWriter> {
+        self.vcd.take()
+    }
+
+    pub fn writing_vcd(&self) -> bool {
+        self.vcd.is_some()
+    }
+
+    /// the index of each `Microcontroller` that hit a `Breakpoint` on the last step
+    /// and the `Breakpoint` it hit
+    pub fn breakpoint_hits(&self) -> &[(usize, Breakpoint)] {
+        &self.breakpoint_hits
+    }
+
+    /// why running should stop after the last step, a breakpoint hit comes before a deadlock
+    /// and a deadlock before every `Microcontroller` having stopped. `None` if it can keep going
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if !self.breakpoint_hits.is_empty() {
+            Some(StopReason::Breakpoint)
+        } else if self.stalled() {
+            Some(StopReason::Deadlock)
+        } else if self.stopped() {
+            Some(StopReason::Halted)
+        } else {
+            None
+        }
+    }
+
+    /// run one cycle, step every `Microcontroller` then pass values along connections,
+    /// returns every value that was passed
+    pub fn step(&mut self) -> Vec<IoTransfer> {
+        self.cycles += 1;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.before_step(&self.mcs);
+        }
+        //compute
+        let mut progressed = false;
+        self.breakpoint_hits.clear();
+        for (i, mc) in self.mcs.iter_mut().enumerate() {
+            progressed |= mc.step();
+            if let Some(bp) = mc.breakpoint_hit() {
+                self.breakpoint_hits.push((i, bp));
+            }
+        }
+        if let Some(vcd) = &mut self.vcd {
+            vcd.record(&self.mcs);
+        }
+
+        let transfers = self.transfer();
+        self.stalled = !progressed && transfers.is_empty();
+        if let Some(tracer) = &mut self.tracer {
+            let stepped : Vec<usize> = (0..self.mcs.len()).collect();
+            tracer.record_step(self.cycles, &self.mcs, &stepped, &transfers);
+        }
+        transfers
+    }
+
+    /// step only the `Microcontroller` at index `i` while the others are frozen, then pass values
+    /// along connections. this isn't a cycle of the circuit so doesn't count towards `cycles`
+    pub fn step_mc(&mut self, i : usize) -> Vec<IoTransfer> {
+        self.breakpoint_hits.clear();
+        if i >= self.mcs.len() {
+            return Vec::new();
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.before_step(&self.mcs);
+        }
+        self.mcs[i].step();
+        if let Some(bp) = self.mcs[i].breakpoint_hit() {
+            self.breakpoint_hits.push((i, bp));
+        }
+        if let Some(vcd) = &mut self.vcd {
+            vcd.record(&self.mcs);
+        }
+        let transfers = self.transfer();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record_step(self.cycles, &self.mcs, &[i], &transfers);
+        }
+        transfers
+    }
+
+    /// pass the value of every output port that has one to the input connected to it, if that
+    /// input is waiting for it. an input can only take one value so if several outputs are
+    /// connected to it the lowest port goes first
+    fn transfer(&mut self) -> Vec<IoTransfer> {
+        let mut read_out_ports : Vec<McConnection> = self.connections.keys()
+            .filter(|io_out| self.io_out_ready(io_out))
+            .copied()
+            .collect();
+        read_out_ports.sort();
+        let mut transfers = Vec::new();
+        for io_out in read_out_ports {
+            let io_in = self.connections[&io_out];
+            if self.io_in_ready(&io_in) {
+                let value = self.mcs[io_out.get_mc_i()].io_read_out(io_out.get_io_i()).unwrap();
+                let read = self.mcs[io_in.get_mc_i()].io_read_in(value, io_in.get_io_i());
+                assert!(read, "input was ready to read in");
+                transfers.push(IoTransfer { from : io_out, to : io_in, value });
+            }
+        }
+        if let Some(vcd) = &mut self.vcd {
+            vcd.record(&self.mcs);
+        }
+        transfers
+    }
+}
+
+/// two compiled microcontrollers with IO1 of the first connected to IO3 of the second, for tests
+#[cfg(test)]
+fn two_mcs(code1 : &str, code2 : &str) -> Simulation {
+    let mut sim = Simulation::new();
+    for code in [code1, code2] {
+        let i = sim.add_mc(McModel::default_model());
+        sim.mc_mut(i).set_code(code.to_string());
+    }
+    sim.connect(McConnection::new(0, 1), McConnection::new(1, 3)).unwrap();
+    assert!(sim.compile().iter().all(|r| r.is_ok()));
+    sim
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microcontroller::assembler::Register;
+    use crate::microcontroller::ExecStats;
+
+    #[test]
+    fn values_pass_along_connections() {
+        let mut sim = two_mcs("ADD #20 #22 IO1\nHLT", "ADD IO3 #0 R1\nHLT");
+        let transfers = sim.step();
+        assert!(transfers == vec![IoTransfer { from : McConnection::new(0, 1), to : McConnection::new(1, 3), value : 42 }]);
+        while !sim.stopped() {
+            sim.step();
+        }
+        assert!(sim.mcs()[1].get_register_value(Register::R1) == Some(42));
+    }
+
+    #[test]
+    fn stall_when_waiting_on_each_other() {
+        let mut sim = two_mcs("ADD IO1 #0 R1\nHLT", "ADD IO3 #0 R1\nHLT");
+        sim.step();
+        assert!(!sim.stalled());
+        assert!(sim.deadlock().is_none());
+        sim.step();
+        assert!(sim.stalled());
+        let blocked = sim.deadlock().unwrap();
+        assert!(blocked == vec![
+            BlockedPort { port : McConnection::new(0, 1), direction : IoDirection::In, partner : Some(McConnection::new(1, 3)) },
+            BlockedPort { port : McConnection::new(1, 3), direction : IoDirection::In, partner : Some(McConnection::new(0, 1)) },
+        ]);
+        assert!(blocked[0].to_string() == "mc 0 waiting to read IO1 from mc 1 IO3");
+    }
+
+    #[test]
+    fn stop_reasons() {
+        let mut sim = two_mcs("ADD IO1 #0 R1\nHLT", "ADD IO3 #0 R1\nHLT");
+        sim.step();
+        assert!(sim.stop_reason().is_none());
+        sim.step();
+        assert!(sim.stop_reason() == Some(StopReason::Deadlock));
+
+        let mut sim = two_mcs("HLT", "HLT");
+        sim.step();
+        assert!(sim.stop_reason() == Some(StopReason::Halted));
+
+        //a breakpoint on the step that halts is reported first
+        let mut sim = two_mcs("HLT", "HLT");
+        sim.mc_mut(0).add_breakpoint("line 1".parse().unwrap());
+        sim.step();
+        assert!(sim.stop_reason() == Some(StopReason::Breakpoint));
+    }
+
+    #[test]
+    fn deadlock_on_unconnected_port() {
+        let mut sim = two_mcs("ADD #1 #0 IO2\nHLT", "HLT");
+        sim.step();
+        sim.step();
+        let blocked = sim.deadlock().unwrap();
+        assert!(blocked.len() == 1);
+        assert!(blocked[0].to_string() == "mc 0 waiting to write IO2 (not connected)");
+    }
+
+    #[test]
+    fn one_step_per_cycle() {
+        let mut sim = two_mcs("ADD #20 #22 IO1\nHLT", "ADD IO3 #0 R1\nADD R1 #1 R1\nHLT");
+        assert!(sim.step().len() == 1);
+        //the value has arrived but the reading instruction finishes next cycle
+        assert!(sim.mcs()[1].get_register_value(Register::PC) == Some(0));
+        assert!(sim.mcs()[1].get_register_value(Register::R1) == Some(0));
+        sim.step();
+        assert!(sim.mcs()[1].get_register_value(Register::PC) == Some(1));
+        assert!(sim.mcs()[1].get_register_value(Register::R1) == Some(42));
+        sim.step();
+        assert!(sim.mcs()[1].get_register_value(Register::R1) == Some(43));
+        assert!(sim.cycles() == 3);
+        assert!(sim.mcs().iter().all(|mc| mc.stats().cycles() == 3));
+        assert!(sim.mcs()[0].stats() == ExecStats { instructions : 2, stopped : 1, ..ExecStats::default() });
+        assert!(sim.mcs()[1].stats() == ExecStats { instructions : 2, read_blocked : 1, ..ExecStats::default() });
+    }
+
+    /// what happened in a run, by the position of each chip in the chain
+    /// so runs with different orders can be compared
+    #[derive(PartialEq, Debug)]
+    struct ChainRun {
+        cycles : usize,
+        /// cycle, from chip, to chip, value
+        transfers : Vec<(usize, usize, usize, i16)>,
+        /// `R1` and if halted
+        states : Vec<(Option<i16>, bool)>,
+    }
+
+    /// run a chain of three chips placed at the indices in `order`
+    fn run_chain(order : [usize ; 3]) -> ChainRun {
+        let codes = [
+            "ADD #1 #0 IO1\nADD #2 #0 IO1\nADD #3 #0 IO1\nHLT",
+            "ADD IO3 #0 R1\nMUL R1 #10 IO1\nADD IO3 #0 R1\nMUL R1 #10 IO1\nADD IO3 #0 R1\nMUL R1 #10 IO1\nHLT",
+            "ADD IO3 #0 R1\nADD IO3 R1 R1\nADD IO3 R1 R1\nHLT",
+        ];
+        let mut sim = Simulation::new();
+        for i in 0..3 {
+            let chip = order.iter().position(|o| *o == i).unwrap();
+            sim.add_mc(McModel::default_model());
+            sim.mc_mut(i).set_code(codes[chip].to_string());
+        }
+        sim.connect(McConnection::new(order[0], 1), McConnection::new(order[1], 3)).unwrap();
+        sim.connect(McConnection::new(order[1], 1), McConnection::new(order[2], 3)).unwrap();
+        assert!(sim.compile().iter().all(|r| r.is_ok()));
+
+        let chip = |con : McConnection| order.iter().position(|o| *o == con.get_mc_i()).unwrap();
+        let mut cycles = 0;
+        let mut transfers = Vec::new();
+        while !sim.stopped() && cycles < 100 {
+            cycles += 1;
+            for t in sim.step() {
+                transfers.push((cycles, chip(t.from), chip(t.to), t.value));
+            }
+        }
+        let states = order.iter().map(|i| (sim.mcs()[*i].get_register_value(Register::R1), sim.mcs()[*i].halted())).collect();
+        ChainRun { cycles, transfers, states }
+    }
+
+    #[test]
+    fn chip_order_does_not_change_outcome() {
+        let expected = run_chain([0, 1, 2]);
+        assert!(expected.states == vec![(Some(0), true), (Some(3), true), (Some(60), true)]);
+        for order in [[0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]] {
+            assert!(run_chain(order) == expected, "order {:?}", order);
+        }
+    }
+
+    #[test]
+    fn breakpoints_hit_when_true() {
+        let mut sim = two_mcs("ADD #3 #0 R1\nloop:\nSUB R1 #1 R1\nCMP R1 #0\nBGT loop\nADD R1 #7 IO1\nHLT", "ADD IO3 #0 R1\nHLT");
+        sim.mc_mut(0).add_breakpoint("line 4".parse().unwrap());
+        sim.mc_mut(0).add_breakpoint("r1 == 1".parse().unwrap());
+        sim.mc_mut(1).add_breakpoint("io1 written".parse().unwrap());
+        sim.mc_mut(0).add_breakpoint("io1 written".parse().unwrap());
+        let mut hits = Vec::new();
+        while !sim.stopped() {
+            sim.step();
+            for (mc, bp) in sim.breakpoint_hits() {
+                hits.push((sim.cycles(), *mc, bp.to_string()));
+            }
+        }
+        let hits : Vec<(usize, usize, &str)> = hits.iter().map(|(c, mc, bp)| (*c, *mc, bp.as_str())).collect();
+        assert!(hits == vec![
+            (3, 0, "line 4"),
+            (5, 0, "R1 == 1"),
+            (6, 0, "line 4"),
+            (9, 0, "line 4"),
+            (11, 0, "io1 written"),
+        ], "{:?}", hits);
+    }
+
+    #[test]
+    fn line_breakpoints_hit_each_time_the_line_starts() {
+        let mut sim = two_mcs("ADD #1 #0 R1\nADD #2 #0 R1\nHLT", "l: BRC l");
+        sim.mc_mut(0).add_breakpoint("line 1".parse().unwrap());
+        sim.mc_mut(1).add_breakpoint("line 1".parse().unwrap());
+        let mut hits = vec![0, 0];
+        for _ in 0..10 {
+            sim.step();
+            for (mc, _) in sim.breakpoint_hits() {
+                hits[*mc] += 1;
+            }
+        }
+        assert!(hits == vec![1, 10], "{:?}", hits);
+
+        //waiting to read in on the line doesn't hit it again
+        let mut sim = two_mcs("NOP\nNOP\nADD #1 #0 IO1\nHLT", "ADD IO3 #0 R1\nHLT");
+        sim.mc_mut(1).add_breakpoint("line 1".parse().unwrap());
+        let mut hit_cycles = Vec::new();
+        while !sim.stopped() {
+            sim.step();
+            if !sim.breakpoint_hits().is_empty() {
+                hit_cycles.push(sim.cycles());
+            }
+        }
+        assert!(hit_cycles == vec![1], "{:?}", hit_cycles);
+    }
+
+    #[test]
+    fn source_line_code_is_what_was_compiled() {
+        let mut sim = two_mcs("ADD #1 #0 R1\n  ADD #2 #0 R1\nHLT", "HLT");
+        sim.step();
+        sim.mc_mut(0).set_code(String::from("edited"));
+        assert!(sim.mcs()[0].source_line() == Some(1));
+        assert!(sim.mcs()[0].source_line_code() == Some("ADD #2 #0 R1"));
+    }
+
+    #[test]
+    fn step_one_mc() {
+        let mut sim = two_mcs("ADD #1 #0 R1\nADD #5 #0 IO1\nHLT", "ADD #2 #0 R1\nADD IO3 R1 R1\nHLT");
+        sim.step_mc(0);
+        assert!(sim.mcs()[0].get_register_value(Register::PC) == Some(1));
+        assert!(sim.mcs()[1].get_register_value(Register::PC) == Some(0));
+        //the value is held until the other mc is ready for it
+        assert!(sim.step_mc(0).is_empty());
+        assert!(sim.step_mc(1).is_empty());
+        assert!(sim.step_mc(1).len() == 1);
+        sim.step_mc(1);
+        assert!(sim.mcs()[1].get_register_value(Register::R1) == Some(7));
+        assert!(sim.mcs()[0].get_register_value(Register::PC) == Some(2));
+        assert!(sim.cycles() == 0);
+    }
+
+    #[test]
+    fn restore_snapshots() {
+        let mut sim = two_mcs("loop:\nADD R1 #1 R1\nPUSH R1\nST R1 [#2]\nADD R1 #0 IO1\nBRC loop", "loop:\nADD IO3 R2 R2\nBRC loop");
+        let mut snapshots = Vec::new();
+        let mut transfers = Vec::new();
+        for _ in 0..20 {
+            snapshots.push(sim.snapshot());
+            transfers.push(sim.step());
+        }
+        let end = sim.snapshot();
+        for _ in 0..20 {
+            sim.restore(&snapshots.pop().unwrap()).unwrap();
+        }
+        assert!(sim.cycles() == 0);
+        assert!(sim.mcs()[1].get_register_value(Register::R2) == Some(0));
+        for t in transfers {
+            assert!(sim.step() == t);
+        }
+        assert!(sim.snapshot() == end);
+        assert!(sim.mcs()[1].get_register_value(Register::R2) == Some(10));
+
+        sim.add_mc(McModel::default_model());
+        assert!(sim.restore(&end).is_err());
+    }
+
+    #[test]
+    fn remove_mc_moves_connections() {
+        let mut sim = two_mcs("HLT", "HLT");
+        sim.add_mc(McModel::default_model());
+        sim.connect(McConnection::new(0, 0), McConnection::new(2, 2)).unwrap();
+        sim.remove_mc(1);
+        assert!(sim.mcs().len() == 2);
+        assert!(sim.connections().len() == 2);
+        assert!(sim.connections()[&McConnection::new(1, 2)] == McConnection::new(0, 0));
+        assert!(sim.add_connection(McConnection::new(2, 0), McConnection::new(0, 0)).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+//! Where a `Tracer` or `VcdWriter` writes to as a `Simulation` runs
+
+use std::io::{self, Write};
+
+/// Writes to `out`, keeping the first error instead of returning it so that
+/// a file that can't be written doesn't stop the `Simulation`
+pub(super) struct Output {
+    out : Box<dyn Write>,
+    /// nothing more is written once there is an error
+    error : Option<io::Error>,
+}
+
+impl Output {
+    pub(super) fn new(out : Box<dyn Write>) -> Self {
+        Output { out, error : None }
+    }
+
+    pub(super) fn write(&mut self, text : &str) {
+        if self.error.is_none() {
+            if let Err(e) = self.out.write_all(text.as_bytes()) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// flush what was written, returns the first error there was writing it
+    pub(super) fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Records a `Simulation` as it runs, one JSON object per line so two runs can be diffed
+//!
+//! each step is written as
+//! `{"cycle":3,"mcs":[{"mc":0,"pc":1,"instr":"ADD R1 #1 R1","state":"ran","changes":{"R1":2}}],"transfers":[{"from":[0,1],"to":[1,3],"value":2}]}`
+//! where `pc` and `instr` are of the instruction that was stepped, `instr` is left out if it
+//! wasn't run, and `state` is `ran`, `read`,
+//! `write` or `stopped` for a chip that finished an instruction, waited to read in, waited for
+//! its value to be read out or has halted or faulted. only registers that changed are in `changes`.
+//! going back to a snapshot is written as `{"cycle":3,"restored":true}`
+
+use super::IoTransfer;
+use super::output::Output;
+use crate::microcontroller::assembler::Register;
+use crate::microcontroller::{Microcontroller, ExecStats};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const TRACED_REGISTERS : [Register ; 3] = [Register::R1, Register::R2, Register::RT];
+
+/// What a `Microcontroller` was like before a step
+struct ChipState {
+    pc : i16,
+    registers : [i16 ; 3],
+    stats : ExecStats,
+}
+
+impl ChipState {
+    fn new(mc : &Microcontroller) -> Self {
+        ChipState {
+            pc : mc.get_register_value(Register::PC).unwrap_or(0),
+            registers : TRACED_REGISTERS.map(|r| mc.get_register_value(r).unwrap_or(0)),
+            stats : mc.stats(),
+        }
+    }
+}
+
+/// Writes a trace of each step of a `Simulation`, set with `Simulation::set_tracer`
+pub struct Tracer {
+    out : Output,
+    before : Vec<ChipState>,
+}
+
+impl Tracer {
+    pub fn new(out : Box<dyn Write>) -> Self {
+        Tracer {
+            out : Output::new(out),
+            before : Vec::new(),
+        }
+    }
+
+    /// trace to a new file, replacing it if it exists
+    pub fn create(path : &Path) -> io::Result<Self> {
+        Ok(Tracer::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// remember the state of the chips about to be stepped
+    pub(super) fn before_step(&mut self, mcs : &[Microcontroller]) {
+        self.before = mcs.iter().map(ChipState::new).collect();
+    }
+
+    /// write a line for a step of the chips at `stepped`, comparing them to `before_step`
+    pub(super) fn record_step(&mut self, cycle : usize, mcs : &[Microcontroller], stepped : &[usize], transfers : &[IoTransfer]) {
+        let mut line = format!("{{\"cycle\":{},\"mcs\":[", cycle);
+        for (n, i) in stepped.iter().enumerate() {
+            if n > 0 {
+                line.push(',');
+            }
+            line.push_str(&self.chip_entry(*i, &mcs[*i]));
+        }
+        line.push_str("],\"transfers\":[");
+        let transfers : Vec<String> = transfers.iter().map(|t| format!(
+            "{{\"from\":[{},{}],\"to\":[{},{}],\"value\":{}}}",
+            t.from.get_mc_i(), t.from.get_io_i(), t.to.get_mc_i(), t.to.get_io_i(), t.value
+        )).collect();
+        line.push_str(&transfers.join(","));
+        line.push_str("]}");
+        self.write_line(&line);
+    }
+
+    pub(super) fn record_restore(&mut self, cycle : usize) {
+        self.write_line(&format!("{{\"cycle\":{},\"restored\":true}}", cycle));
+    }
+
+    fn chip_entry(&self, i : usize, mc : &Microcontroller) -> String {
+        let before = match self.before.get(i) {
+            Some(b) => b,
+            None => return format!("{{\"mc\":{}}}", i),
+        };
+        let stats = mc.stats();
+        let state = if stats.instructions > before.stats.instructions {
+            "ran"
+        } else if stats.read_blocked > before.stats.read_blocked {
+            "read"
+        } else if stats.write_blocked > before.stats.write_blocked {
+            "write"
+        } else {
+            "stopped"
+        };
+        //a chip waiting to write has already finished the instruction
+        let instr = usize::try_from(before.pc).ok()
+            .filter(|_| state == "ran" || state == "read")
+            .and_then(|pc| mc.line(pc))
+            .map(|l| format!(",\"instr\":\"{}\"", l))
+            .unwrap_or_default();
+        let changes : Vec<String> = TRACED_REGISTERS.iter().zip(before.registers)
+            .filter_map(|(reg, old)| match mc.get_register_value(*reg) {
+                Some(new) if new != old => Some(format!("\"{}\":{}", reg, new)),
+                _ => None,
+            })
+            .collect();
+        format!("{{\"mc\":{},\"pc\":{}{},\"state\":\"{}\",\"changes\":{{{}}}}}", i, before.pc, instr, state, changes.join(","))
+    }
+
+    fn write_line(&mut self, line : &str) {
+        self.out.write(&format!("{}\n", line));
+    }
+
+    /// flush the trace, returns the first error there was writing it
+    pub fn finish(self) -> io::Result<()> {
+        self.out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::two_mcs;
+
+    use std::fs;
+
+    fn trace_run(path : &Path) -> String {
+        let mut sim = two_mcs("ADD #20 #22 IO1\nHLT", "ADD IO3 #0 R1\nSUB R1 #2 R1\nHLT");
+        sim.set_tracer(Tracer::create(path).unwrap());
+        while !sim.stopped() {
+            sim.step();
+        }
+        sim.take_tracer().unwrap().finish().unwrap();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn trace_lines() {
+        let path = std::env::temp_dir().join(format!("zl001-trace-{}.jsonl", std::process::id()));
+        let trace = trace_run(&path);
+        let lines : Vec<&str> = trace.lines().collect();
+        assert!(lines.len() == 4);
+        assert!(lines[0] == concat!(
+            r#"{"cycle":1,"mcs":[{"mc":0,"pc":0,"instr":"ADD #20 #22 IO1","state":"ran","changes":{}},"#,
+            r#"{"mc":1,"pc":0,"instr":"ADD IO3 #0 R1","state":"read","changes":{}}],"#,
+            r#""transfers":[{"from":[0,1],"to":[1,3],"value":42}]}"#));
+        assert!(lines[2] == concat!(
+            r#"{"cycle":3,"mcs":[{"mc":0,"pc":2,"state":"stopped","changes":{}},"#,
+            r#"{"mc":1,"pc":1,"instr":"SUB R1 #2 R1","state":"ran","changes":{"R1":40}}],"transfers":[]}"#));
+        //the same circuit gives the same trace
+        assert!(trace_run(&path) == trace);
+        fs::remove_file(path).unwrap();
+    }
+}
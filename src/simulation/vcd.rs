@@ -0,0 +1,261 @@
+//! Writes the io ports of a `Simulation` as a Value Change Dump, to view in a waveform viewer
+//! such as GTKWave
+//!
+//! every microcontroller is a scope `mcN` holding three signals for each port:
+//! `ioN` the 16 bit value in the port, `ioN_read_ready` set while it waits to read a value in
+//! and `ioN_write_ready` set while a value written to it waits to be read out.
+//! each step is two time units counted from when the writer was set, the first after every chip
+//! has computed and the second after values were passed along connections, so a port that is
+//! only ready to read in until the value arrives is seen. going back to a snapshot carries on
+//! from the time it was at. a microcontroller keeps its scope if others are removed, and ones
+//! added after the dump started aren't in it
+
+use super::output::Output;
+use crate::microcontroller::Microcontroller;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The state of a port, in the order its signals are given identifiers
+#[derive(Copy, Clone, PartialEq)]
+struct PortState {
+    value : i16,
+    read_ready : bool,
+    write_ready : bool,
+}
+
+impl PortState {
+    fn new(mc : &Microcontroller, io : usize) -> Self {
+        PortState {
+            value : mc.io_value(io),
+            read_ready : mc.io_read_in_ready(io),
+            write_ready : mc.io_read_out_ready(io),
+        }
+    }
+}
+
+/// the short name of a signal in the dump, from the printable characters `!` to `~`
+fn identifier(mut n : usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
+
+/// The ports of a microcontroller in the dump
+struct ChipPorts {
+    /// the identifier number of the first signal of the first port
+    first_id : usize,
+    /// the state of every port after the last step
+    ports : Vec<PortState>,
+}
+
+/// Writes a change to the dump for every port that changed on a step,
+/// set with `Simulation::set_vcd`
+pub struct VcdWriter {
+    out : Output,
+    /// the ports of each microcontroller at the same index in the `Simulation`,
+    /// `None` if it was added after the dump started
+    chips : Vec<Option<ChipPorts>>,
+    time : usize,
+}
+
+impl VcdWriter {
+    pub fn new(out : Box<dyn Write>) -> Self {
+        VcdWriter {
+            out : Output::new(out),
+            chips : Vec::new(),
+            time : 0,
+        }
+    }
+
+    /// write to a new file, replacing it if it exists
+    pub fn create(path : &Path) -> io::Result<Self> {
+        Ok(VcdWriter::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// write the signals of every port and their values at time 0
+    pub(super) fn start(&mut self, mcs : &[Microcontroller]) {
+        let mut id = 0;
+        self.chips = mcs.iter()
+            .map(|mc| {
+                let ports : Vec<PortState> = (0..mc.io_count()).map(|io| PortState::new(mc, io)).collect();
+                let chip = ChipPorts { first_id : id, ports };
+                id += 3 * chip.ports.len();
+                Some(chip)
+            })
+            .collect();
+        let mut text = String::from("$version zl001 $end\n$timescale 1 us $end\n$scope module circuit $end\n");
+        for (mc_i, chip) in self.chips.iter().flatten().enumerate() {
+            text.push_str(&format!("$scope module mc{} $end\n", mc_i));
+            for io in 0..chip.ports.len() {
+                let id = chip.first_id + 3 * io;
+                text.push_str(&format!("$var wire 16 {} io{} $end\n", identifier(id), io));
+                text.push_str(&format!("$var wire 1 {} io{}_read_ready $end\n", identifier(id + 1), io));
+                text.push_str(&format!("$var wire 1 {} io{}_write_ready $end\n", identifier(id + 2), io));
+            }
+            text.push_str("$upscope $end\n");
+        }
+        text.push_str("$upscope $end\n$enddefinitions $end\n#0\n$dumpvars\n");
+        for chip in self.chips.iter().flatten() {
+            for (io, port) in chip.ports.iter().enumerate() {
+                text.push_str(&port_changes(chip.first_id + 3 * io, None, port));
+            }
+        }
+        text.push_str("$end\n");
+        self.out.write(&text);
+    }
+
+    /// a microcontroller was added to the `Simulation`, it isn't written to the dump
+    pub(super) fn add_mc(&mut self) {
+        self.chips.push(None);
+    }
+
+    /// the microcontroller at `i` was removed, the last one moving to its index
+    pub(super) fn remove_mc(&mut self, i : usize) {
+        if i < self.chips.len() {
+            self.chips.swap_remove(i);
+        }
+    }
+
+    /// every microcontroller was removed
+    pub(super) fn clear_mcs(&mut self) {
+        self.chips.clear();
+    }
+
+    /// write every port that changed since the last record, called after each phase of a step
+    pub(super) fn record(&mut self, mcs : &[Microcontroller]) {
+        self.time += 1;
+        let mut changes = String::new();
+        for (mc, chip) in mcs.iter().zip(self.chips.iter_mut()) {
+            let chip = match chip {
+                Some(c) => c,
+                None => continue,
+            };
+            for (io, port) in chip.ports.iter_mut().enumerate() {
+                let new = PortState::new(mc, io);
+                changes.push_str(&port_changes(chip.first_id + 3 * io, Some(port), &new));
+                *port = new;
+            }
+        }
+        if !changes.is_empty() {
+            self.out.write(&format!("#{}\n{}", self.time, changes));
+        }
+    }
+
+    /// flush the dump, returns the first error there was writing it
+    pub fn finish(self) -> io::Result<()> {
+        self.out.finish()
+    }
+}
+
+/// the lines for each signal of a port that differs from `old`,
+/// `id` is the identifier number of the port's first signal
+fn port_changes(id : usize, old : Option<&PortState>, new : &PortState) -> String {
+    let mut text = String::new();
+    if old.map(|o| o.value) != Some(new.value) {
+        text.push_str(&format!("b{:016b} {}\n", new.value as u16, identifier(id)));
+    }
+    if old.map(|o| o.read_ready) != Some(new.read_ready) {
+        text.push_str(&format!("{}{}\n", new.read_ready as u8, identifier(id + 1)));
+    }
+    if old.map(|o| o.write_ready) != Some(new.write_ready) {
+        text.push_str(&format!("{}{}\n", new.write_ready as u8, identifier(id + 2)));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{Simulation, two_mcs};
+    use crate::microcontroller::McModel;
+
+    use std::collections::HashMap;
+    use std::fs;
+
+    /// read a dump back into the changes of each signal, by `scope.name`, as (time, value)
+    fn parse_vcd(text : &str) -> HashMap<String, Vec<(usize, i64)>> {
+        let mut names = HashMap::new();
+        let mut signals : HashMap<String, Vec<(usize, i64)>> = HashMap::new();
+        let mut scope = String::new();
+        let mut time = 0;
+        for line in text.lines() {
+            let words : Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["$scope", "module", name, "$end"] => scope = name.to_string(),
+                ["$var", "wire", _, id, name, "$end"] => { names.insert(id.to_string(), format!("{}.{}", scope, name)); },
+                [t] if t.starts_with('#') => time = t[1..].parse().unwrap(),
+                [value, id] if value.starts_with('b') => {
+                    let value = u16::from_str_radix(&value[1..], 2).unwrap() as i16;
+                    signals.entry(names[*id].clone()).or_default().push((time, value as i64));
+                },
+                [change] if change.starts_with('0') || change.starts_with('1') => {
+                    let (value, id) = change.split_at(1);
+                    signals.entry(names[id].clone()).or_default().push((time, value.parse().unwrap()));
+                },
+                _ => (),
+            }
+        }
+        signals
+    }
+
+    #[test]
+    fn identifiers() {
+        assert!(identifier(0) == "!");
+        assert!(identifier(93) == "~");
+        assert!(identifier(94) == "!!");
+        assert!(identifier(95) == "\"!");
+    }
+
+    #[test]
+    fn vcd_round_trip() {
+        let path = std::env::temp_dir().join(format!("zl001-vcd-{}.vcd", std::process::id()));
+        let mut sim = two_mcs("ADD #-20 #0 IO1\nHLT", "NOP\nNOP\nADD IO3 #0 R1\nHLT");
+        sim.set_vcd(VcdWriter::create(&path).unwrap());
+        while !sim.stopped() {
+            sim.step();
+        }
+        sim.take_vcd().unwrap().finish().unwrap();
+
+        let signals = parse_vcd(&fs::read_to_string(&path).unwrap());
+        fs::remove_file(path).unwrap();
+        assert!(signals.len() == 2 * 4 * 3);
+        //mc 0 writes on the first step, mc 1 is ready for it on the third
+        assert!(signals["mc0.io1"] == vec![(0, 0), (1, -20)]);
+        assert!(signals["mc0.io1_write_ready"] == vec![(0, 0), (1, 1), (6, 0)]);
+        assert!(signals["mc1.io3_read_ready"] == vec![(0, 0), (5, 1), (6, 0)]);
+        assert!(signals["mc1.io3"] == vec![(0, 0), (6, -20)]);
+        assert!(signals["mc0.io0"] == vec![(0, 0)]);
+    }
+
+    #[test]
+    fn removed_mcs_keep_their_signals() {
+        let path = std::env::temp_dir().join(format!("zl001-vcd-remove-{}.vcd", std::process::id()));
+        let mut sim = Simulation::new();
+        for code in ["HLT", "ADD #1 #0 IO1\nHLT", "ADD #2 #0 IO1\nHLT"] {
+            let i = sim.add_mc(McModel::default_model());
+            sim.mc_mut(i).set_code(code.to_string());
+        }
+        assert!(sim.compile().iter().all(|r| r.is_ok()));
+        sim.set_vcd(VcdWriter::create(&path).unwrap());
+        //the added mc isn't in the dump and moves to index 0, then mc 2 moves to index 1
+        sim.add_mc(McModel::default_model());
+        sim.remove_mc(0);
+        sim.remove_mc(1);
+        sim.step();
+        sim.take_vcd().unwrap().finish().unwrap();
+
+        let signals = parse_vcd(&fs::read_to_string(&path).unwrap());
+        fs::remove_file(path).unwrap();
+        assert!(signals["mc0.io1"] == vec![(0, 0)]);
+        assert!(signals["mc1.io1"] == vec![(0, 0)]);
+        assert!(signals["mc2.io1"] == vec![(0, 0), (1, 2)]);
+    }
+}